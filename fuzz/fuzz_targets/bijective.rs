@@ -0,0 +1,36 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_arithmetic_coding::{
+    codec::{BijectiveDecoder, BijectiveEncoder},
+    Input, Output,
+};
+
+// Every byte string decodes, and encoding the message gives it back. The
+// first byte picks the alphabet size.
+fuzz_target!(|data: &[u8]| {
+    let Some((&control, coded)) = data.split_first() else {
+        return;
+    };
+    let size = control as usize + 2;
+    let mut bits_in = Input::new(coded).unwrap();
+    let mut decoder = BijectiveDecoder::new(size);
+    decoder.begin(&mut bits_in).unwrap();
+    let mut message = Vec::new();
+    while let Some(s) = decoder.decode(&mut bits_in).unwrap() {
+        decoder.discover(s);
+        message.push(s);
+    }
+
+    let mut recoded = Vec::new();
+    let mut bits_out = Output::new(&mut recoded, 64);
+    let mut encoder = BijectiveEncoder::new(size);
+    for &s in message.iter() {
+        encoder.encode(s, &mut bits_out).unwrap();
+        encoder.discover(s);
+    }
+    encoder.finish(&mut bits_out).unwrap();
+    bits_out.flush().unwrap();
+    drop(bits_out);
+    assert_eq!(recoded, coded);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_arithmetic_coding::{decode_routine_with, DecodeOptions};
+
+// Any input must decode or fail with an error, within the size limit.
+fuzz_target!(|data: &[u8]| {
+    let options = DecodeOptions { max_size: Some(1 << 20) };
+    let mut decoded = Vec::new();
+    if decode_routine_with(&mut &data[..], &mut decoded, &options).is_ok() {
+        assert!(decoded.len() <= 1 << 20);
+    }
+});
@@ -0,0 +1,65 @@
+#![no_main]
+
+use std::io::BufReader;
+
+use libfuzzer_sys::fuzz_target;
+use simple_arithmetic_coding::{Input, Next};
+
+// Reads the input back through chunks of every size with a mix of bit,
+// byte and varint reads, which must agree with the bytes themselves.
+fuzz_target!(|data: &[u8]| {
+    let Some((&control, data)) = data.split_first() else {
+        return;
+    };
+    let chunk = (control & 0x1f) as usize + 1;
+    let mut bits_in = Input::new(BufReader::with_capacity(chunk, data)).unwrap();
+    let bit = |i: usize| data[i / 8] >> (7 - i % 8) & 1 != 0;
+    let mut i = 0;
+    let mut step = control >> 5;
+    loop {
+        step = step.wrapping_mul(5).wrapping_add(1);
+        match step % 3 {
+            0 => match bits_in.next_bit().unwrap() {
+                Some(b) => {
+                    assert_eq!(b, bit(i));
+                    i += 1;
+                }
+                None => break,
+            },
+            1 => match bits_in.next_byte().unwrap() {
+                Some(byte) => {
+                    for k in 0..8 {
+                        assert_eq!(byte >> (7 - k) & 1 != 0, bit(i + k));
+                    }
+                    i += 8;
+                }
+                None => break,
+            },
+            _ => {
+                let mut value = 0;
+                let mut expected = None;
+                let mut read = 0;
+                for shft in (0..64).step_by(7) {
+                    if i + 8 > data.len() * 8 {
+                        break;
+                    }
+                    let byte = (i..i + 8).fold(0u8, |byte, k| byte << 1 | bit(k) as u8);
+                    i += 8;
+                    read += 1;
+                    value |= ((byte & 0x7f) as u64) << shft;
+                    if byte & 0x80 == 0 {
+                        expected = Some(value);
+                        break;
+                    }
+                }
+                match bits_in.next_varint() {
+                    Ok(value) => assert_eq!(Some(value), expected),
+                    // Too long rather than truncated: reading goes on.
+                    Err(_) if read == 10 => {}
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+    assert!(data.len() * 8 - i < 8);
+});
@@ -0,0 +1,244 @@
+//! Secondary symbol estimation with adaptive probability maps.
+//!
+//! An [`Apm`] takes a bit probability from some primary model and refines
+//! it in a small context: for every context it keeps a piecewise linear map
+//! from the stretched input probability to an output probability, learned
+//! from the bits actually coded. It corrects systematic errors of the
+//! primary model, e.g. being overconfident right after a mispredicted bit.
+//! It works with any source of 12-bit probabilities, such as a
+//! [`BitModel`](crate::binary::BitModel) or the mixer of
+//! [`CmModel`](crate::cm::CmModel).
+//! [`SseModel`] brings it to any frequency [`Model`], by coding each
+//! symbol as the binary decisions that spell it out.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::apm::Apm;
+//! use simple_arithmetic_coding::binary::{BinaryDecoder, BinaryEncoder, BitModel, PROB_ONE};
+//!
+//! let bits: Vec<bool> = (0..1000).map(|i| i % 3 == 0).collect();
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = BinaryEncoder::new();
+//! let (mut model, mut apm) = (BitModel::new(), Apm::new(3, 6));
+//! for (i, &bit) in bits.iter().enumerate() {
+//!     let p1 = apm.refine((PROB_ONE - model.p0()) as u32, i % 3);
+//!     encoder.encode_with_probability((PROB_ONE as u32 - p1) as u16, bit, &mut bits_out)?;
+//!     apm.update(bit);
+//!     model.update(bit);
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = BinaryDecoder::new();
+//! let (mut model, mut apm) = (BitModel::new(), Apm::new(3, 6));
+//! decoder.begin(&mut bits_in)?;
+//! for (i, &bit) in bits.iter().enumerate() {
+//!     let p1 = apm.refine((PROB_ONE - model.p0()) as u32, i % 3);
+//!     assert_eq!(decoder.decode_with_probability((PROB_ONE as u32 - p1) as u16, &mut bits_in)?, bit);
+//!     apm.update(bit);
+//!     model.update(bit);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{
+    binary::PROB_ONE,
+    cm::{squash, Stretch},
+    codec::{ArithmeticDecoder, ArithmeticEncoder, CodeValue},
+    io,
+    model::Model
+};
+
+// Every context maps stretched probabilities through 33 evenly spaced
+// points, 128 apart, covering -2048..=2048.
+const POINTS: usize = 33;
+
+/// An adaptive probability map over a fixed number of contexts.
+pub struct Apm {
+    table: Box<[u16]>,
+    stretch: Stretch,
+    rate: u32,
+    index: usize
+}
+
+impl Apm {
+    /// Creates a map over `contexts` contexts that starts as the identity
+    /// and moves `1 / 2^rate` of the way towards every coded bit.
+    pub fn new (contexts: usize, rate: u32) -> Self {
+        assert!(contexts > 0 && rate > 0 && rate < 16);
+        let row: Vec<u16> = (0..POINTS)
+            .map(|j| (squash((j as i32 - 16) * 128) * 16) as u16)
+            .collect();
+        Apm {
+            table: row.iter().copied().cycle().take(contexts * POINTS).collect(),
+            stretch: Stretch::new(),
+            rate,
+            index: 0
+        }
+    }
+
+    /// Returns the refined probability of a one for the 12-bit probability
+    /// `p1` in context `cx`. Must be followed by [`Apm::update`] with the
+    /// coded bit.
+    pub fn refine (&mut self, p1: u32, cx: usize) -> u32 {
+        let s = (self.stretch.get(p1.min(PROB_ONE as u32 - 1)) + 2048) as usize;
+        let (point, w) = (s >> 7, (s & 127) as u32);
+        let row = cx * POINTS;
+        self.index = row + point + (w >> 6) as usize;
+        let p = (self.table[row + point] as u32 * (128 - w) + self.table[row + point + 1] as u32 * w) >> 11;
+        p.clamp(1, PROB_ONE as u32 - 1)
+    }
+
+    /// Trains the point nearest the last refined probability on `bit`.
+    pub fn update (&mut self, bit: bool) {
+        let target: i32 = if bit { 65535 } else { 0 };
+        let t = &mut self.table[self.index];
+        *t = (*t as i32 + ((target - *t as i32) >> self.rate)) as u16;
+    }
+}
+
+/// Codes the symbols of any [`Model`] as a path of binary decisions, most
+/// significant bit first, and refines the probability the model's
+/// frequencies give every decision through an [`Apm`] whose context is the
+/// bits of the symbol coded so far.
+pub struct SseModel<M>
+where M: Model
+{
+    model: M,
+    apm: Apm,
+    bits: u32
+}
+
+impl<M> SseModel<M>
+where M: Model
+{
+    /// Creates a refinement of `model`, which must have at least two symbols.
+    pub fn new (model: M) -> Self {
+        assert!(model.size() > 1);
+        let bits = usize::BITS - (model.size() - 1).leading_zeros();
+        SseModel { apm: Apm::new(1 << bits, 7), model, bits }
+    }
+
+    /// Returns the underlying model.
+    pub fn model (&self) -> &M {
+        &self.model
+    }
+
+    // The cumulative frequency below `s`, which may lie past the alphabet.
+    fn below (&self, s: usize) -> u32 {
+        if s < self.model.size() { self.model.cum_freq(s).0 } else { self.model.total() }
+    }
+
+    // Where a zero ends out of PROB_ONE for the decision after the bits in
+    // `node`, with `level` more to come: 0 or PROB_ONE when the decision is
+    // already settled, as one side holds no symbols.
+    fn split (&mut self, node: usize, level: u32) -> u32 {
+        let depth = self.bits - 1 - level;
+        let start = (node - (1 << depth)) << (level + 1);
+        let (lower, middle, upper) = (self.below(start), self.below(start + (1 << level)), self.below(start + (2 << level)));
+        if middle == upper || middle == lower {
+            return if middle == upper { PROB_ONE as u32 } else { 0 };
+        }
+        let p1 = ((upper - middle) as u64 * PROB_ONE as u64 / (upper - lower) as u64) as u32;
+        let p1 = p1.clamp(1, PROB_ONE as u32 - 1);
+        let refined = self.apm.refine(p1, node);
+        PROB_ONE as u32 - (p1 + 3 * refined) / 4
+    }
+
+    /// Encodes `s` and records it in the model.
+    pub fn encode<C, W> (&mut self, s: usize, encoder: &mut ArithmeticEncoder<C, W>, bits_out: &mut impl io::Push) -> Result<(), std::io::Error>
+    where C: Model, W: CodeValue
+    {
+        let one = PROB_ONE as u32;
+        let mut node = 1;
+        for level in (0..self.bits).rev() {
+            let bit = (s >> level) & 1 != 0;
+            let split = self.split(node, level);
+            if split != 0 && split != one {
+                let (lower, upper) = if bit { (split, one) } else { (0, split) };
+                encoder.encode_interval(lower, upper, one, bits_out)?;
+                self.apm.update(bit);
+            }
+            node = 2 * node + bit as usize;
+        }
+        self.model.update(s);
+        Ok(())
+    }
+
+    /// Decodes the next symbol and records it in the model.
+    pub fn decode<C, W> (&mut self, decoder: &mut ArithmeticDecoder<C, W>, bits_in: &mut impl io::Next) -> Result<usize, std::io::Error>
+    where C: Model, W: CodeValue
+    {
+        let one = PROB_ONE as u32;
+        let mut node = 1;
+        for level in (0..self.bits).rev() {
+            let split = self.split(node, level);
+            let bit = if split == 0 || split == one {
+                split == 0
+            } else {
+                let bit = decoder.decode_count(one)? >= split;
+                let (lower, upper) = if bit { (split, one) } else { (0, split) };
+                decoder.decode_interval(lower, upper, one, bits_in)?;
+                self.apm.update(bit);
+                bit
+            };
+            node = 2 * node + bit as usize;
+        }
+        let s = node - (1 << self.bits);
+        self.model.update(s);
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apm_identity () {
+        let mut apm = Apm::new(2, 7);
+        for p in [1, 100, 1000, 2047, 3000, 4000, 4095] {
+            assert!((apm.refine(p, 1) as i32 - p as i32).abs() <= p as i32 / 16 + 8);
+        }
+    }
+    #[test]
+    fn test_apm_learns () {
+        // The input claims 50/50, but context 0 always sees ones and
+        // context 1 always zeros.
+        let mut apm = Apm::new(2, 5);
+        for i in 0..2000 {
+            apm.refine(2048, i % 2);
+            apm.update(i % 2 == 0);
+        }
+        assert!(apm.refine(2048, 0) > 3900);
+        assert!(apm.refine(2048, 1) < 200);
+    }
+    #[test]
+    fn test_sse_model () {
+        use crate::{model::AdaptiveModel, Input, Output};
+
+        // Five symbols leave some decisions settled by the alphabet alone.
+        let message: Vec<usize> = (0..5000).map(|i| if i % 7 == 3 { 4 } else { i % 2 }).collect();
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = ArithmeticEncoder::new(1);
+        let mut model = SseModel::new(AdaptiveModel::new(5));
+        for &s in message.iter() {
+            model.encode(s, &mut encoder, &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::new(1);
+        let mut model = SseModel::new(AdaptiveModel::new(5));
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(model.decode(&mut decoder, &mut bits_in).unwrap(), s);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! An adaptive binary arithmetic coder.
+//!
+//! This is the LZMA-style range coder specialised to binary decisions. Each
+//! decision is coded against a [`BitModel`], a 12-bit probability that moves
+//! a fixed fraction towards every observed bit, so there is no frequency
+//! table to search or rescale. Multi-bit values are coded as a path through
+//! a [`BitTree`] of decisions, and equiprobable "bypass" bits skip the model
+//! altogether. The coder renormalizes a byte at a time through the
+//! [`Push`](io::Push) and [`Next`](io::Next) traits.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::binary::{BinaryDecoder, BinaryEncoder, BitModel, BitTree};
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = BinaryEncoder::new();
+//! let (mut flag, mut bytes) = (BitModel::new(), BitTree::new(8));
+//! encoder.encode_bit(&mut flag, true, &mut bits_out)?;
+//! bytes.encode(&mut encoder, b'x' as u32, &mut bits_out)?;
+//! encoder.encode_direct(0b101, 3, &mut bits_out)?;
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = BinaryDecoder::new();
+//! let (mut flag, mut bytes) = (BitModel::new(), BitTree::new(8));
+//! decoder.begin(&mut bits_in)?;
+//! assert!(decoder.decode_bit(&mut flag, &mut bits_in)?);
+//! assert_eq!(bytes.decode(&mut decoder, &mut bits_in)?, b'x' as u32);
+//! assert_eq!(decoder.decode_direct(3, &mut bits_in)?, 0b101);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{error::Error, io};
+
+/// Probabilities are fixed point numbers with this many fractional bits.
+pub const PROB_BITS: u32 = 12;
+/// The probability scale, i.e. a probability of one.
+pub const PROB_ONE: u16 = 1 << PROB_BITS;
+const MOVE_BITS: u32 = 4;
+const TOP: u32 = 1 << 24;
+
+/// The adaptive probability that the next bit is zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitModel {
+    p: u16
+}
+
+impl BitModel {
+    /// Creates a model with both bit values equally likely.
+    pub fn new () -> Self {
+        BitModel { p: PROB_ONE / 2 }
+    }
+
+    /// Returns the probability of a zero bit, out of [`PROB_ONE`].
+    pub fn p0 (&self) -> u16 {
+        self.p
+    }
+
+    /// Moves the probability 1/16 of the way towards `bit`.
+    pub fn update (&mut self, bit: bool) {
+        if bit {
+            self.p -= self.p >> MOVE_BITS;
+        } else {
+            self.p += (PROB_ONE - self.p) >> MOVE_BITS;
+        }
+    }
+}
+
+impl Default for BitModel {
+    fn default () -> Self {
+        BitModel::new()
+    }
+}
+
+/// Encodes binary decisions into a byte sink.
+pub struct BinaryEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64
+}
+
+impl BinaryEncoder {
+    /// Creates an encoder at the start of a stream.
+    pub fn new () -> Self {
+        BinaryEncoder {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1
+        }
+    }
+
+    /// Encodes `bit` with `model` and adapts the model to it.
+    pub fn encode_bit (&mut self, model: &mut BitModel, bit: bool, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        self.encode_with_probability(model.p, bit, bits_out)?;
+        model.update(bit);
+        Ok(())
+    }
+
+    /// Encodes `bit` given a probability `p0` of zero out of [`PROB_ONE`],
+    /// which must lie strictly between zero and one; panics otherwise.
+    pub fn encode_with_probability (&mut self, p0: u16, bit: bool, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        assert!(p0 > 0 && p0 < PROB_ONE, "probability out of range");
+        let bound = (self.range >> PROB_BITS) * p0 as u32;
+        if bit {
+            self.low += bound as u64;
+            self.range -= bound;
+        } else {
+            self.range = bound;
+        }
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low(bits_out)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the low `count` bits of `value`, most significant first, as
+    /// equiprobable bypass bits.
+    pub fn encode_direct (&mut self, value: u32, count: u32, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        for i in (0..count).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 != 0 {
+                self.low += self.range as u64;
+            }
+            while self.range < TOP {
+                self.range <<= 8;
+                self.shift_low(bits_out)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Bytes are held back while they could still be changed by a carry out
+    // of `low`: `cache` is the oldest pending byte, followed by
+    // `cache_size - 1` bytes of 0xff.
+    fn shift_low (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        if self.low < 0xff00_0000 || self.low > 0xffff_ffff {
+            let carry = (self.low >> 32) as u8;
+            let mut temp = self.cache;
+            loop {
+                bits_out.push_byte(temp.wrapping_add(carry))?;
+                temp = 0xff;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00ff_ffff) << 8;
+        Ok(())
+    }
+
+    /// Writes out the remaining state. The sink still has to be flushed
+    /// afterwards.
+    pub fn finish (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        for _ in 0..5 {
+            self.shift_low(bits_out)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for BinaryEncoder {
+    fn default () -> Self {
+        BinaryEncoder::new()
+    }
+}
+
+/// Decodes binary decisions produced by a [`BinaryEncoder`].
+pub struct BinaryDecoder {
+    range: u32,
+    code: u32
+}
+
+impl BinaryDecoder {
+    /// Creates a decoder; call [`BinaryDecoder::begin`] before decoding.
+    pub fn new () -> Self {
+        BinaryDecoder {
+            range: u32::MAX,
+            code: 0
+        }
+    }
+
+    /// Reads the first bytes of the stream. Must be called once before
+    /// decoding.
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error> {
+        for _ in 0..5 {
+            self.code = (self.code << 8) | next_byte(bits_in)? as u32;
+        }
+        Ok(())
+    }
+
+    /// Decodes a bit with `model` and adapts the model to it.
+    pub fn decode_bit (&mut self, model: &mut BitModel, bits_in: &mut impl io::Next) -> Result<bool, Error> {
+        let bit = self.decode_with_probability(model.p, bits_in)?;
+        model.update(bit);
+        Ok(bit)
+    }
+
+    /// Decodes a bit given a probability `p0` of zero out of [`PROB_ONE`],
+    /// mirroring [`BinaryEncoder::encode_with_probability`].
+    pub fn decode_with_probability (&mut self, p0: u16, bits_in: &mut impl io::Next) -> Result<bool, Error> {
+        assert!(p0 > 0 && p0 < PROB_ONE, "probability out of range");
+        if self.code >= self.range {
+            return Err(Error::corrupt("code value outside the coding range"));
+        }
+        let bound = (self.range >> PROB_BITS) * p0 as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            false
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            true
+        };
+        self.normalize(bits_in)?;
+        Ok(bit)
+    }
+
+    /// Decodes `count` bypass bits, most significant first.
+    pub fn decode_direct (&mut self, count: u32, bits_in: &mut impl io::Next) -> Result<u32, Error> {
+        let mut value = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = self.code >= self.range;
+            if bit {
+                self.code -= self.range;
+            }
+            value = (value << 1) | bit as u32;
+            self.normalize(bits_in)?;
+        }
+        Ok(value)
+    }
+
+    fn normalize (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error> {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | next_byte(bits_in)? as u32;
+        }
+        Ok(())
+    }
+}
+
+impl Default for BinaryDecoder {
+    fn default () -> Self {
+        BinaryDecoder::new()
+    }
+}
+
+/// Codes `bits`-bit values as a path of binary decisions, most significant
+/// bit first, with one [`BitModel`] per node of the tree. A tree of eight
+/// bits codes a byte.
+#[derive(Clone)]
+pub struct BitTree {
+    models: Vec<BitModel>,
+    bits: u32
+}
+
+impl BitTree {
+    /// Creates a tree of `bits` levels, between 1 and 24, with even models.
+    pub fn new (bits: u32) -> Self {
+        assert!(bits > 0 && bits <= 24);
+        BitTree {
+            models: vec![BitModel::new(); 1 << bits],
+            bits
+        }
+    }
+
+    /// Encodes the low `bits` bits of `value`.
+    pub fn encode (&mut self, encoder: &mut BinaryEncoder, value: u32, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        let mut node = 1;
+        for i in (0..self.bits).rev() {
+            let bit = (value >> i) & 1 != 0;
+            encoder.encode_bit(&mut self.models[node], bit, bits_out)?;
+            node = (node << 1) | bit as usize;
+        }
+        Ok(())
+    }
+
+    /// Decodes a value of `bits` bits.
+    pub fn decode (&mut self, decoder: &mut BinaryDecoder, bits_in: &mut impl io::Next) -> Result<u32, Error> {
+        let mut node = 1;
+        for _ in 0..self.bits {
+            let bit = decoder.decode_bit(&mut self.models[node], bits_in)?;
+            node = (node << 1) | bit as usize;
+        }
+        Ok((node - (1 << self.bits)) as u32)
+    }
+}
+
+// The decoder reads exactly the bytes the encoder wrote.
+fn next_byte (bits_in: &mut impl io::Next) -> Result<u8, Error> {
+    bits_in.next_byte()?.ok_or(Error::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    #[test]
+    fn test_bit_model_bounds () {
+        let mut model = BitModel::new();
+        for _ in 0..1000 {
+            model.update(true);
+        }
+        assert!(model.p0() > 0);
+        for _ in 0..1000 {
+            model.update(false);
+        }
+        assert!(model.p0() < PROB_ONE);
+    }
+    #[test]
+    fn test_binary_round_trip () {
+        let bits: Vec<bool> = (0..20000).map(|i| i % 23 == 0 || i % 7 == 3).collect();
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = BinaryEncoder::new();
+        let mut model = BitModel::new();
+        let mut tree = BitTree::new(8);
+        for (i, &b) in bits.iter().enumerate() {
+            encoder.encode_bit(&mut model, b, &mut bits_out).unwrap();
+            if i % 100 == 0 {
+                tree.encode(&mut encoder, (i / 100) as u32 & 0xff, &mut bits_out).unwrap();
+                encoder.encode_direct(i as u32, 17, &mut bits_out).unwrap();
+            }
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = BinaryDecoder::new();
+        let mut model = BitModel::new();
+        let mut tree = BitTree::new(8);
+        decoder.begin(&mut bits_in).unwrap();
+        for (i, &b) in bits.iter().enumerate() {
+            assert_eq!(decoder.decode_bit(&mut model, &mut bits_in).unwrap(), b);
+            if i % 100 == 0 {
+                assert_eq!(tree.decode(&mut decoder, &mut bits_in).unwrap(), (i / 100) as u32 & 0xff);
+                assert_eq!(decoder.decode_direct(17, &mut bits_in).unwrap(), i as u32 & 0x1ffff);
+            }
+        }
+    }
+    #[test]
+    fn test_binary_skewed () {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = BinaryEncoder::new();
+        let mut model = BitModel::new();
+        for i in 0..80000 {
+            encoder.encode_bit(&mut model, i % 200 == 0, &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        assert!(coded.len() < 80000 / 8 / 10);
+    }
+    #[test]
+    #[should_panic]
+    fn test_binary_certain () {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        BinaryEncoder::new().encode_with_probability(PROB_ONE, true, &mut bits_out).unwrap();
+    }
+    #[test]
+    fn test_binary_carry () {
+        // Long runs of ones push `low` up against the carry boundary.
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = BinaryEncoder::new();
+        let mut model = BitModel::new();
+        for i in 0..50000 {
+            encoder.encode_bit(&mut model, i % 1000 != 999, &mut bits_out).unwrap();
+            encoder.encode_direct(0xffff_ffff, 32, &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = BinaryDecoder::new();
+        let mut model = BitModel::new();
+        decoder.begin(&mut bits_in).unwrap();
+        for i in 0..50000 {
+            assert_eq!(decoder.decode_bit(&mut model, &mut bits_in).unwrap(), i % 1000 != 999);
+            assert_eq!(decoder.decode_direct(32, &mut bits_in).unwrap(), 0xffff_ffff);
+        }
+    }
+}
@@ -35,18 +35,44 @@ impl BitVec {
         self.data.len()*64
     }
 
-    pub fn push (&mut self, bit: bool) -> () {
+    pub fn push (&mut self, bit: bool) {
         assert!(self.i < self.capacity());
         let bit = bit as u64;
-        self.data[self.i/64] |= bit << self.i%64;
+        self.data[self.i/64] |= bit << (self.i%64);
         self.i+=1;
     }
 
+    pub fn push_byte (&mut self, byte: u8) {
+        if self.i % 8 != 0 {
+            for shft in (0..8).rev() {
+                self.push(((byte >> shft) & 1) != 0);
+            }
+            return;
+        }
+        assert!(self.i + 8 <= self.capacity());
+        self.data[self.i/64] |= (byte.reverse_bits() as u64) << (self.i%64);
+        self.i+=8;
+    }
+
+    pub fn get_byte (&self, idx: usize) -> Option<u8> {
+        if idx + 8 > self.i {
+            return None
+        }
+        if idx % 8 != 0 {
+            let mut byte = 0;
+            for k in idx..idx+8 {
+                byte = (byte << 1) | self.get(k)? as u8;
+            }
+            return Some(byte)
+        }
+        Some(((self.data[idx/64] >> (idx%64)) as u8).reverse_bits())
+    }
+
     pub fn get (&self, idx: usize) -> Option<bool> {
         if idx >= self.i {
             return None
         }
-        Some((self.data[idx/64] >> idx%64) & 1 != 0)
+        Some((self.data[idx/64] >> (idx%64)) & 1 != 0)
     }
 
     pub fn is_empty (&self) -> bool {
@@ -57,7 +83,7 @@ impl BitVec {
         self.i
     }
 
-    pub fn clear(&mut self) -> () {
+    pub fn clear(&mut self) {
         self.i = 0;
         self.data.fill(0);
     }
@@ -133,7 +159,7 @@ mod tests {
     }
     #[test]
     fn test_next_bit_1() {
-        let bytes = vec![
+        let bytes = [
             0b00000001,
             0b00000010,
             0b11111111,
@@ -185,9 +211,35 @@ mod tests {
         }
     }
     #[test]
+    fn test_push_byte_1() {
+        let bytes = [0x00, 0x80, 0x01, 0xa5, 0xff, 0x3c, 0x11, 0x42, 0x99];
+        let mut bitvec = BitVec::with_capacity(128);
+        bitvec.push(true);
+        for b in bytes.iter() {
+            bitvec.push_byte(*b);
+        }
+        for (k, b) in bytes.iter().enumerate() {
+            assert_eq!(bitvec.get_byte(1 + k*8), Some(*b));
+        }
+        assert_eq!(bitvec.get_byte(bytes.len()*8 - 6), None);
+    }
+    #[test]
+    fn test_push_byte_2() {
+        let bytes = "aligned bytes take the fast path".as_bytes();
+        let mut bitvec = BitVec::with_capacity(bytes.len()*8);
+        for b in bytes.iter() {
+            bitvec.push_byte(*b);
+        }
+        assert_eq!(bitvec.get_bytes(), bytes);
+        let bitvec = BitVec::from_bytes(bytes);
+        for (k, b) in bytes.iter().enumerate() {
+            assert_eq!(bitvec.get_byte(k*8), Some(*b));
+        }
+    }
+    #[test]
     fn test_next_message() {
         let bytes = "asdoijfasöodfjaosidjfioasd".as_bytes();
-        let bitvec = BitVec::from_bytes(&bytes);
+        let bitvec = BitVec::from_bytes(bytes);
         assert_eq!(bytes, bitvec.get_bytes());
     }
 }
@@ -0,0 +1,416 @@
+//! Block sorting: the Burrows-Wheeler transform with move-to-front and
+//! zero-run coding.
+//!
+//! The input is split into blocks of up to a configurable size. Every block
+//! is permuted by the Burrows-Wheeler transform, built from a suffix array,
+//! which groups bytes by the context that follows them, so that the result
+//! consists of long stretches of few distinct bytes. Move-to-front turns
+//! these into mostly small numbers and runs of zeros, the runs are written
+//! in bijective base two with the symbols `RUNA` and `RUNB` as in bzip2,
+//! and the symbols are coded with adaptive models by the CACM87 coder.
+//! Every block starts with its length and primary index; a block of length
+//! zero ends the stream.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::bwt::{BwtDecoder, BwtEncoder};
+//!
+//! let data = b"banana bandana, banana bandana, banana bandana".to_vec();
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! BwtEncoder::new(16).encode(&data, &mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut decoded, 64);
+//! BwtDecoder::new().decode(&mut bits_in, &mut bits_out)?;
+//! bits_out.flush()?;
+//! drop(bits_out);
+//! assert_eq!(decoded, data);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{
+    codec::{ArithmeticDecoder, ArithmeticEncoder},
+    error::Error,
+    io,
+    model::{AdaptiveModel, Model}
+};
+
+/// The largest supported block.
+pub const MAX_BLOCK_SIZE: usize = 1 << 24;
+/// The block size used unless another is asked for.
+pub const DEFAULT_BLOCK_SIZE: usize = 900_000;
+
+// The symbols after move-to-front: RUNA and RUNB are the digits of a run
+// of zeros, and the other ranks are shifted up by one to make room.
+const RUNA: usize = 0;
+const RUNB: usize = 1;
+pub(crate) const SYMBOLS: usize = 257;
+// Symbols are coded in one of four contexts, by whether the previous one
+// was RUNA, RUNB, a rank of 1 or a higher rank.
+const CONTEXTS: usize = 4;
+// Block headers are coded flat, at most this many bits at a time.
+const HEADER_CHUNK: u32 = 12;
+const HEADER_BITS: u32 = 32;
+
+/// Returns the suffix array of `data`: the starting positions of all its
+/// suffixes in lexicographic order, where a suffix sorts before any longer
+/// string it is a prefix of.
+///
+/// Suffixes are sorted by prefix doubling: knowing the order of their
+/// first `k` bytes, two stable counting sorts order them by their first
+/// `2k`, until all are told apart.
+pub fn suffix_array (data: &[u8]) -> Vec<usize> {
+    let n = data.len();
+    let mut sa: Vec<usize> = (0..n).collect();
+    sa.sort_by_key(|&i| data[i]);
+    // Ranks start at one, so that zero stands for the empty suffix.
+    let mut rank: Vec<usize> = data.iter().map(|&b| b as usize + 1).collect();
+    let mut next = vec![0; n];
+    let mut counts = vec![0; n.max(256) + 2];
+    let mut k = 1;
+    if n < 2 {
+        return sa;
+    }
+    loop {
+        let key = |i: usize| (rank[i], if i + k < n { rank[i + k] } else { 0 });
+        // By the second half: the suffixes that have none, then the rest
+        // in the order of the suffixes their second half starts.
+        let mut by_second: Vec<usize> = (n.saturating_sub(k)..n).collect();
+        by_second.extend(sa.iter().filter(|&&i| i >= k).map(|&i| i - k));
+        // Then stably by the first half.
+        counts.fill(0);
+        for &i in by_second.iter() {
+            counts[rank[i] + 1] += 1;
+        }
+        for r in 1..counts.len() {
+            counts[r] += counts[r - 1];
+        }
+        for &i in by_second.iter() {
+            sa[counts[rank[i]]] = i;
+            counts[rank[i]] += 1;
+        }
+        next[sa[0]] = 1;
+        for w in 1..n {
+            next[sa[w]] = next[sa[w - 1]] + (key(sa[w]) != key(sa[w - 1])) as usize;
+        }
+        std::mem::swap(&mut rank, &mut next);
+        if rank[sa[n - 1]] == n {
+            break;
+        }
+        k *= 2;
+    }
+    sa
+}
+
+/// Returns the Burrows-Wheeler transform of `data` and its primary index.
+///
+/// The transform is the last column of the sorted rotations of `data`
+/// followed by an end marker that sorts first; the marker itself is left
+/// out, and the primary index is the row it would be in.
+pub fn bwt (data: &[u8]) -> (Vec<u8>, usize) {
+    let n = data.len();
+    let mut last = Vec::with_capacity(n);
+    let mut primary = 0;
+    if n > 0 {
+        last.push(data[n - 1]);
+    }
+    for (row, &i) in suffix_array(data).iter().enumerate() {
+        if i == 0 {
+            primary = row + 1;
+        } else {
+            last.push(data[i - 1]);
+        }
+    }
+    (last, primary)
+}
+
+/// Inverts [`bwt`]. Returns `None` if `primary` is out of range.
+pub fn inverse_bwt (last: &[u8], primary: usize) -> Option<Vec<u8>> {
+    let n = last.len();
+    if primary > n || (primary == 0 && n > 0) {
+        return None;
+    }
+    // The rank of every row's last byte among equal bytes, and where the
+    // rows starting with every byte begin, after the marker's row.
+    let mut start = [0usize; 256];
+    let mut ranks = Vec::with_capacity(n);
+    for &b in last.iter() {
+        ranks.push(start[b as usize]);
+        start[b as usize] += 1;
+    }
+    let mut sum = 1;
+    for s in start.iter_mut() {
+        let count = *s;
+        *s = sum;
+        sum += count;
+    }
+    // Walks the text backwards from the marker's row. Row `r` of the table
+    // is `last[r - 1]` after the primary row and `last[r]` before it.
+    let mut data = vec![0; n];
+    let mut row = 0;
+    for out in data.iter_mut().rev() {
+        let i = if row < primary { row } else { row - 1 };
+        *out = last[i];
+        row = start[last[i] as usize] + ranks[i];
+    }
+    Some(data)
+}
+
+fn encode_symbol (model: &mut AdaptiveModel, s: usize, coder: &mut ArithmeticEncoder, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+    let (lower, upper) = model.cum_freq(s);
+    coder.encode_interval(lower, upper, model.total(), bits_out)?;
+    model.update(s);
+    Ok(())
+}
+
+fn decode_symbol (model: &mut AdaptiveModel, coder: &mut ArithmeticDecoder, bits_in: &mut impl io::Next) -> Result<usize, std::io::Error> {
+    let s = model.symbol(coder.decode_count(model.total())?);
+    let (lower, upper) = model.cum_freq(s);
+    coder.decode_interval(lower, upper, model.total(), bits_in)?;
+    model.update(s);
+    Ok(s)
+}
+
+fn context (s: usize) -> usize {
+    s.min(CONTEXTS - 1)
+}
+
+/// Splits its input into blocks, transforms and codes them.
+pub struct BwtEncoder {
+    models: Vec<AdaptiveModel>,
+    coder: ArithmeticEncoder,
+    block_size: usize
+}
+
+impl BwtEncoder {
+    /// Creates an encoder for blocks of up to `block_size` bytes.
+    pub fn new (block_size: usize) -> Self {
+        assert!(block_size > 0 && block_size <= MAX_BLOCK_SIZE);
+        BwtEncoder {
+            models: vec![AdaptiveModel::new(SYMBOLS); CONTEXTS],
+            coder: ArithmeticEncoder::new(1),
+            block_size
+        }
+    }
+
+    /// Returns the largest block size.
+    pub fn block_size (&self) -> usize {
+        self.block_size
+    }
+
+    fn encode_header (&mut self, value: usize, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        let mut bits = HEADER_BITS;
+        while bits > 0 {
+            let n = bits.min(HEADER_CHUNK);
+            bits -= n;
+            let v = (value as u32 >> bits) & ((1 << n) - 1);
+            self.coder.encode_interval(v, v + 1, 1 << n, bits_out)?;
+        }
+        Ok(())
+    }
+
+    /// Codes all of `data`, then finishes the stream. The sink still has to
+    /// be flushed afterwards.
+    pub fn encode (&mut self, data: &[u8], bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        for block in data.chunks(self.block_size) {
+            self.encode_block(block, bits_out)?;
+        }
+        self.finish(bits_out)
+    }
+
+    /// Transforms and codes one block of at most the block size.
+    pub fn encode_block (&mut self, block: &[u8], bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        assert!(!block.is_empty() && block.len() <= self.block_size);
+        let (last, primary) = bwt(block);
+        self.encode_header(block.len(), bits_out)?;
+        self.encode_header(primary, bits_out)?;
+
+        let mut order: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let mut previous = 0;
+        let mut run = 0;
+        for &b in last.iter() {
+            let rank = order.iter().position(|&c| c == b).unwrap();
+            if rank == 0 {
+                run += 1;
+                continue;
+            }
+            self.encode_run(run, &mut previous, bits_out)?;
+            run = 0;
+            order.copy_within(0..rank, 1);
+            order[0] = b;
+            encode_symbol(&mut self.models[context(previous)], rank + 1, &mut self.coder, bits_out)?;
+            previous = rank + 1;
+        }
+        self.encode_run(run, &mut previous, bits_out)
+    }
+
+    // Codes a run of zeros in bijective base two, least significant digit
+    // first.
+    fn encode_run (&mut self, mut run: usize, previous: &mut usize, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        while run > 0 {
+            run -= 1;
+            let digit = if run & 1 == 0 { RUNA } else { RUNB };
+            encode_symbol(&mut self.models[context(*previous)], digit, &mut self.coder, bits_out)?;
+            *previous = digit;
+            run >>= 1;
+        }
+        Ok(())
+    }
+
+    /// Codes the end of the stream and the final bits.
+    pub fn finish (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        self.encode_header(0, bits_out)?;
+        self.coder.finish(bits_out)
+    }
+}
+
+/// Decodes a stream written by a [`BwtEncoder`].
+pub struct BwtDecoder {
+    models: Vec<AdaptiveModel>,
+    coder: ArithmeticDecoder
+}
+
+impl BwtDecoder {
+    /// Creates a decoder; call [`BwtDecoder::begin`] before decoding.
+    pub fn new () -> Self {
+        BwtDecoder {
+            models: vec![AdaptiveModel::new(SYMBOLS); CONTEXTS],
+            coder: ArithmeticDecoder::new(1)
+        }
+    }
+
+    fn decode_header (&mut self, bits_in: &mut impl io::Next) -> Result<usize, std::io::Error> {
+        let mut bits = HEADER_BITS;
+        let mut value = 0;
+        while bits > 0 {
+            let n = bits.min(HEADER_CHUNK);
+            bits -= n;
+            let v = self.coder.decode_count(1 << n)?;
+            self.coder.decode_interval(v, v + 1, 1 << n, bits_in)?;
+            value = (value << n) | v as usize;
+        }
+        Ok(value)
+    }
+
+    /// Reads the first bits of the stream. Must be called once before
+    /// [`BwtDecoder::decode_block`].
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), std::io::Error> {
+        Ok(self.coder.begin(bits_in)?)
+    }
+
+    /// Decodes the next block, or `None` at the end of the stream.
+    pub fn decode_block (&mut self, bits_in: &mut impl io::Next) -> Result<Option<Vec<u8>>, std::io::Error> {
+        let invalid = |message| std::io::Error::from(Error::corrupt(message));
+        let len = self.decode_header(bits_in)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        if len > MAX_BLOCK_SIZE {
+            return Err(invalid("block too large"));
+        }
+        let primary = self.decode_header(bits_in)?;
+
+        let mut order: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let mut last = Vec::with_capacity(len);
+        let mut previous = 0;
+        let mut run = 0;
+        let mut digit = 1;
+        while last.len() + run < len {
+            let s = decode_symbol(&mut self.models[context(previous)], &mut self.coder, bits_in)?;
+            previous = s;
+            if s == RUNA || s == RUNB {
+                run += (s + 1) * digit;
+                digit <<= 1;
+                continue;
+            }
+            last.resize(last.len() + run, order[0]);
+            run = 0;
+            digit = 1;
+            let rank = s - 1;
+            let b = order[rank];
+            order.copy_within(0..rank, 1);
+            order[0] = b;
+            last.push(b);
+        }
+        if last.len() + run > len {
+            return Err(invalid("block longer than its length"));
+        }
+        last.resize(last.len() + run, order[0]);
+        inverse_bwt(&last, primary).map(Some).ok_or_else(|| invalid("primary index out of range"))
+    }
+
+    /// Decodes the whole stream into `bits_out`.
+    pub fn decode (&mut self, bits_in: &mut impl io::Next, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        self.begin(bits_in)?;
+        while let Some(block) = self.decode_block(bits_in)? {
+            for b in block {
+                bits_out.push_byte(b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for BwtDecoder {
+    fn default () -> Self {
+        BwtDecoder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    fn round_trip (data: &[u8], block_size: usize) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        BwtEncoder::new(block_size).encode(data, &mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut decoded, 800);
+        BwtDecoder::new().decode(&mut bits_in, &mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        assert_eq!(decoded, data);
+        coded
+    }
+
+    #[test]
+    fn test_suffix_array () {
+        assert_eq!(suffix_array(b"banana"), [5, 3, 1, 0, 4, 2]);
+        assert_eq!(suffix_array(b"aaaa"), [3, 2, 1, 0]);
+        assert_eq!(suffix_array(b""), [0usize; 0]);
+    }
+    #[test]
+    fn test_bwt () {
+        // The rotations of "banana$" sorted, with '$' first, end in
+        // "annb$aa".
+        assert_eq!(bwt(b"banana"), (b"annbaa".to_vec(), 4));
+        for data in [&b""[..], b"a", b"abracadabra", b"mississippi", b"zzzzzzzz"] {
+            let (last, primary) = bwt(data);
+            assert_eq!(inverse_bwt(&last, primary).unwrap(), data);
+        }
+        assert_eq!(inverse_bwt(b"annbaa", 7), None);
+    }
+    #[test]
+    fn test_bwt_round_trip () {
+        round_trip(&[], 1);
+        round_trip(b"x", 1);
+        let mut data = b"she sells sea shells by the sea shore. ".repeat(100);
+        data.extend((0..3000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8));
+        data.extend([0; 1000]);
+        for block_size in [1, 100, 4096, DEFAULT_BLOCK_SIZE] {
+            let coded = round_trip(&data, block_size);
+            if block_size > 100 {
+                assert!(coded.len() < data.len() / 2);
+            }
+        }
+    }
+}
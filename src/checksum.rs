@@ -0,0 +1,323 @@
+//! CRC-32 checksums of stream headers and of the data a stream codes.
+//!
+//! [`Checksummed`] wraps a bit source or sink and keeps the CRC-32 of the
+//! bytes that pass through it. [`Trailer`] wraps a bit source whose stream
+//! is followed by four bytes of checksum and withholds them from the
+//! reader, so a decoder that reads past the end of its own bits still stops
+//! where it would without them.
+//!
+//! ```
+//! use simple_arithmetic_coding::checksum::crc32;
+//!
+//! assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+//! ```
+
+use crate::{error::Error, io::{Next, Push}};
+
+// The reflected IEEE 802.3 polynomial, as used by zlib and PNG.
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// A running CRC-32.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32 {
+    state: u32
+}
+
+impl Default for Crc32 {
+    fn default () -> Self {
+        Crc32::new()
+    }
+}
+
+impl Crc32 {
+    /// Creates the checksum of no bytes.
+    pub fn new () -> Self {
+        Crc32 { state: !0 }
+    }
+
+    /// Adds `bytes` to the checksum.
+    pub fn update (&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = TABLE[((self.state ^ byte as u32) & 0xff) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    /// Returns the checksum of the bytes added so far.
+    pub fn value (&self) -> u32 {
+        !self.state
+    }
+}
+
+/// Returns the CRC-32 of `bytes`.
+pub fn crc32 (bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.value()
+}
+
+/// The part of a stream whose checksum did not match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checked {
+    /// The stream header.
+    Header,
+    /// The decoded data.
+    Data,
+}
+
+/// A checksum that does not match, which decoding returns as
+/// [`Error::Checksum`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChecksumError {
+    /// What the checksum covers.
+    pub part: Checked,
+    /// The checksum stored in the stream.
+    pub stored: u32,
+    /// The checksum of what was read or decoded.
+    pub computed: u32,
+}
+
+impl std::fmt::Display for ChecksumError {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let part = match self.part {
+            Checked::Header => "header",
+            Checked::Data => "data",
+        };
+        write!(f, "{part} checksum mismatch: stored {:08x}, computed {:08x}", self.stored, self.computed)
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+impl ChecksumError {
+    /// Returns an error unless `stored` and `computed` are equal.
+    pub fn check (part: Checked, stored: u32, computed: u32) -> Result<(), std::io::Error> {
+        if stored == computed {
+            return Ok(());
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, ChecksumError { part, stored, computed }))
+    }
+}
+
+/// A bit source or sink that keeps the CRC-32 of the whole bytes read from
+/// or written to it.
+pub struct Checksummed<T> {
+    inner: T,
+    crc: Crc32,
+    partial: u8,
+    bits: u32
+}
+
+impl<T> Checksummed<T> {
+    /// Wraps `inner`.
+    pub fn new (inner: T) -> Self {
+        Checksummed { inner, crc: Crc32::new(), partial: 0, bits: 0 }
+    }
+
+    /// Returns the checksum of the whole bytes that have passed so far.
+    pub fn crc (&self) -> u32 {
+        self.crc.value()
+    }
+
+    /// Returns the wrapped source or sink.
+    pub fn into_inner (self) -> T {
+        self.inner
+    }
+
+    fn add_bit (&mut self, bit: bool) {
+        self.partial = (self.partial << 1) | bit as u8;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.crc.update(&[self.partial]);
+            self.bits = 0;
+        }
+    }
+
+    fn add_byte (&mut self, byte: u8) {
+        if self.bits == 0 {
+            self.crc.update(&[byte]);
+            return;
+        }
+        for shft in (0..8).rev() {
+            self.add_bit((byte >> shft) & 1 != 0);
+        }
+    }
+}
+
+impl<N> Next for Checksummed<N>
+where N: Next
+{
+    fn next_bit (&mut self) -> Result<Option<bool>, std::io::Error> {
+        let bit = self.inner.next_bit()?;
+        if let Some(bit) = bit {
+            self.add_bit(bit);
+        }
+        Ok(bit)
+    }
+
+    fn next_byte (&mut self) -> Result<Option<u8>, std::io::Error> {
+        let byte = self.inner.next_byte()?;
+        if let Some(byte) = byte {
+            self.add_byte(byte);
+        }
+        Ok(byte)
+    }
+}
+
+impl<P> Push for Checksummed<P>
+where P: Push
+{
+    fn push_bit(&mut self, bit: bool) -> Result<(), std::io::Error> {
+        self.add_bit(bit);
+        self.inner.push_bit(bit)
+    }
+
+    fn push_byte(&mut self, byte: u8) -> Result<(), std::io::Error> {
+        self.add_byte(byte);
+        self.inner.push_byte(byte)
+    }
+}
+
+/// A bit source that withholds the 32 bits after whatever is read from
+/// it, and at the end of `N` the last 32 bits of `N`.
+pub struct Trailer<N> {
+    inner: N,
+    held: u32,
+    // Bits passed on, for skipping to a byte boundary.
+    passed: u64
+}
+
+impl<N> Trailer<N>
+where N: Next
+{
+    /// Wraps `inner`, reading ahead the 32 bits that may be the trailer.
+    /// Byte boundaries are counted from here.
+    pub fn new (mut inner: N) -> Result<Self, std::io::Error> {
+        let mut held = 0;
+        for _ in 0..32 {
+            let bit = inner.next_bit()?.ok_or(Error::Truncated)?;
+            held = (held << 1) | bit as u32;
+        }
+        Ok(Trailer { inner, held, passed: 0 })
+    }
+
+    /// Skips the padding up to the next byte boundary, where the trailer
+    /// starts, and returns it. The bits after it are left unread.
+    pub fn finish (&mut self) -> Result<u32, std::io::Error> {
+        while self.passed % 8 != 0 && self.next_bit()?.is_some() {}
+        Ok(self.held)
+    }
+
+    /// Returns the wrapped source.
+    pub fn into_inner (self) -> N {
+        self.inner
+    }
+}
+
+impl<N> Next for Trailer<N>
+where N: Next
+{
+    fn next_bit (&mut self) -> Result<Option<bool>, std::io::Error> {
+        Ok(self.inner.next_bit()?.map(|bit| {
+            let out = self.held >> 31 != 0;
+            self.held = (self.held << 1) | bit as u32;
+            self.passed += 1;
+            out
+        }))
+    }
+
+    fn next_byte (&mut self) -> Result<Option<u8>, std::io::Error> {
+        let mut byte: u8 = 0;
+        for _ in 0..8 {
+            match self.next_bit()? {
+                Some(bit) => byte = (byte << 1) | bit as u8,
+                None => return Ok(None)
+            }
+        }
+        Ok(Some(byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use crate::{Input, Output};
+
+    #[test]
+    fn test_crc32 () {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.value(), 0xcbf4_3926);
+    }
+    #[test]
+    fn test_checksummed () {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Checksummed::new(Output::new(&mut coded, 64));
+        bits_out.push_bit(true).unwrap();
+        for &byte in b"bits" {
+            bits_out.push_byte(byte).unwrap();
+        }
+        for _ in 0..7 {
+            bits_out.push_bit(false).unwrap();
+        }
+        assert_eq!(bits_out.crc(), crc32(&coded_bytes(true, b"bits")));
+        let crc = bits_out.crc();
+        bits_out.into_inner().flush().unwrap();
+
+        let mut bits_in = Checksummed::new(Input::new(coded.as_slice()).unwrap());
+        while bits_in.next_byte().unwrap().is_some() {}
+        assert_eq!(bits_in.crc(), crc);
+    }
+
+    // `bytes` shifted right by one bit behind `first`.
+    fn coded_bytes (first: bool, bytes: &[u8]) -> Vec<u8> {
+        let mut carry = first as u8;
+        let mut shifted: Vec<u8> = bytes.iter().map(|&b| {
+            let out = (carry << 7) | (b >> 1);
+            carry = b & 1;
+            out
+        }).collect();
+        shifted.push(carry << 7);
+        shifted
+    }
+    #[test]
+    fn test_trailer () {
+        let coded = b"bo\x01\x02\x03\x04tail";
+        let mut bits_in = Trailer::new(Input::new(&coded[..]).unwrap()).unwrap();
+        assert_eq!(bits_in.next_bit().unwrap(), Some(false));
+        assert_eq!(bits_in.next_byte().unwrap(), Some(b'b' << 1));
+        assert_eq!(bits_in.finish().unwrap(), 0x0102_0304);
+        let mut rest = Vec::new();
+        bits_in.into_inner().into_inner().read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"tail");
+        assert!(Trailer::new(Input::new(&coded[..3]).unwrap()).is_err());
+    }
+    #[test]
+    fn test_checksum_error () {
+        assert!(ChecksumError::check(Checked::Data, 7, 7).is_ok());
+        let err = ChecksumError::check(Checked::Data, 7, 8).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let inner = err.get_ref().and_then(|e| e.downcast_ref::<ChecksumError>()).unwrap();
+        assert_eq!(inner.part, Checked::Data);
+    }
+}
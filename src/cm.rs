@@ -0,0 +1,409 @@
+//! A bitwise context-mixing model for the [`binary`](crate::binary) coder.
+//!
+//! Bytes are coded as eight binary decisions, most significant bit first.
+//! For every decision, each of several context models looks up an adaptive
+//! probability: orders 0 to 6, the current word, and a sparse
+//! context made of the second and third preceding bytes. A [`MatchModel`]
+//! adds a prediction from the longest earlier repeat of the latest bytes. A
+//! logistic mixer combines them in the stretched (`ln(p / (1 - p))`) domain
+//! with weights trained online to minimise coding cost, and the result is a
+//! 12-bit probability for the coder. This is slow and takes about 150 MB of
+//! tables, but compresses far better than any single context.
+//!
+//! All arithmetic is integer, so the encoder and decoder agree on every
+//! platform.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::binary::{BinaryDecoder, BinaryEncoder};
+//! use simple_arithmetic_coding::cm::CmModel;
+//!
+//! let message = b"mixing many contexts, mixing many contexts";
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = BinaryEncoder::new();
+//! let mut model = CmModel::new();
+//! for &b in message.iter() {
+//!     model.encode(b, &mut encoder, &mut bits_out)?;
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = BinaryDecoder::new();
+//! let mut model = CmModel::new();
+//! decoder.begin(&mut bits_in)?;
+//! for &b in message.iter() {
+//!     assert_eq!(model.decode(&mut decoder, &mut bits_in)?, b);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{
+    apm::Apm,
+    binary::{BinaryDecoder, BinaryEncoder, PROB_ONE},
+    io,
+    match_model::MatchModel
+};
+
+/// The number of context models mixed.
+pub const CONTEXTS: usize = 9;
+// Context model `i` has a table of 1 << TABLE_BITS[i] counters. Orders 0
+// and 1 fit in full; the rest are large enough that, on megabytes of text,
+// colliding contexts rarely share counters.
+const TABLE_BITS: [u32; CONTEXTS] = [13, 18, 22, 22, 22, 22, 22, 22, 22];
+// Counters stop slowing their adaptation after this many updates.
+const COUNT_LIMIT: u32 = 255;
+// Mixer inputs: the stretched prediction of every context model, the same
+// again faded out over a counter's first YOUNG updates, so the mixer can
+// trust fresh counters differently, the match model's prediction and its
+// expected bit scaled by the match length, and a constant bias.
+const INPUTS: usize = 2 * CONTEXTS + 3;
+const YOUNG: u32 = 32;
+const LEARNING_RATE: i32 = 5;
+// Weights are 16.16 fixed point and stay within ±16, which keeps every
+// product in the mixer within range however skewed the input.
+const MAX_WEIGHT: i32 = 1 << 20;
+// The mixer keeps a weight set per partial byte, class of the preceding
+// byte (letter, space, digit or anything else) and whether a match is
+// being followed.
+const MIXER_SETS: usize = 2 * 4 * 256;
+// The SSE stage remembers whether each of this many recent predictions was
+// on the right side of one half.
+const HIT_BITS: u32 = 4;
+
+// The optional SSE stage: one map by partial byte and recent hits, one by
+// partial byte and preceding byte.
+struct Sse {
+    hits: Apm,
+    order1: Apm,
+    history: u32
+}
+
+const SQUASH_POINTS: [i32; 33] = [
+    1, 2, 3, 6, 10, 16, 27, 45, 73, 120, 194, 310, 488, 747, 1101, 1546, 2047,
+    2549, 2994, 3348, 3607, 3785, 3901, 3975, 4024, 4050, 4068, 4079, 4085, 4089, 4092, 4093, 4094
+];
+
+/// Maps a stretched probability `d` (in 1/256ths, clamped to ±2047) to a
+/// 12-bit probability, `4096 / (1 + e^(-d / 256))`.
+pub fn squash (d: i32) -> i32 {
+    if d > 2047 {
+        return 4095;
+    }
+    if d < -2047 {
+        return 0;
+    }
+    let w = d & 127;
+    let i = ((d >> 7) + 16) as usize;
+    (SQUASH_POINTS[i] * (128 - w) + SQUASH_POINTS[i + 1] * w + 64) >> 7
+}
+
+/// The inverse of [`squash`] for 12-bit probabilities.
+pub struct Stretch {
+    table: Box<[i16]>
+}
+
+impl Stretch {
+    /// Creates the table by inverting [`squash`].
+    pub fn new () -> Self {
+        let mut table = vec![2047i16; PROB_ONE as usize];
+        let mut next = 0;
+        for d in -2047..=2047 {
+            let p = squash(d) as usize;
+            for t in table[next..=p].iter_mut() {
+                *t = d as i16;
+            }
+            next = p + 1;
+        }
+        Stretch { table: table.into_boxed_slice() }
+    }
+
+    /// Returns `ln(p / (1 - p))` in 1/256ths for a 12-bit probability `p`.
+    pub fn get (&self, p: u32) -> i32 {
+        self.table[p as usize] as i32
+    }
+}
+
+impl Default for Stretch {
+    fn default () -> Self {
+        Stretch::new()
+    }
+}
+
+// A counter holds a 22-bit probability of a one above a 10-bit count of
+// updates. It moves 1/(n + 1.5) of the way towards each bit, so it learns
+// quickly at first and settles as evidence accumulates.
+const COUNTER_INIT: u32 = 1 << 31;
+
+fn counter_p (counter: u32) -> u32 {
+    counter >> 20
+}
+
+fn counter_update (counter: &mut u32, bit: bool, reciprocals: &[i32]) {
+    let n = *counter & 1023;
+    let p = (*counter >> 10) as i64;
+    let target = if bit { (1 << 22) - 1 } else { 0 };
+    let p = p + (((target - p) * reciprocals[n as usize] as i64) >> 16);
+    *counter = (p as u32) << 10 | (n + 1).min(COUNT_LIMIT);
+}
+
+fn hash (value: u64, model: usize) -> u32 {
+    let h = (value ^ (model as u64) << 56).wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (h >> 32) as u32
+}
+
+/// Predicts bytes one bit at a time by mixing several context models.
+pub struct CmModel {
+    tables: Vec<Box<[u32]>>,
+    hashes: [u32; CONTEXTS],
+    bases: [usize; CONTEXTS],
+    slots: [usize; CONTEXTS],
+    inputs: [i32; INPUTS],
+    weights: Box<[i32]>,
+    stretch: Stretch,
+    reciprocals: Box<[i32]>,
+    matcher: MatchModel,
+    set: usize,
+    c0: u32,
+    class: usize,
+    bits: u32,
+    history: u64,
+    word: u64,
+    mixed: u32,
+    sse: Option<Sse>,
+    pr: u32
+}
+
+impl CmModel {
+    /// Creates a model whose mixer output goes straight to the coder.
+    pub fn new () -> Self {
+        CmModel::with_sse(false)
+    }
+
+    /// Creates a model that, if `sse` is set, refines the mixer output with
+    /// two [`Apm`] stages before coding.
+    pub fn with_sse (sse: bool) -> Self {
+        let mut model = CmModel {
+            tables: TABLE_BITS.iter().map(|&bits| vec![COUNTER_INIT; 1 << bits].into_boxed_slice()).collect(),
+            hashes: [0; CONTEXTS],
+            bases: [0; CONTEXTS],
+            slots: [0; CONTEXTS],
+            inputs: [0; INPUTS],
+            weights: vec![(1 << 16) / CONTEXTS as i32; MIXER_SETS * INPUTS].into_boxed_slice(),
+            stretch: Stretch::new(),
+            reciprocals: (0..1024).map(|n| (65536.0 * 2.0 / (2 * n + 3) as f64) as i32).collect(),
+            matcher: MatchModel::new(),
+            set: 0,
+            c0: 1,
+            class: 0,
+            bits: 0,
+            history: 0,
+            word: 0,
+            mixed: 2048,
+            sse: sse.then(|| Sse {
+                hits: Apm::new(256 << HIT_BITS, 7),
+                order1: Apm::new(256 * 256, 7),
+                history: 0
+            }),
+            pr: 2048
+        };
+        model.next_byte();
+        model.predict();
+        model
+    }
+
+    /// Returns the probability, out of [`PROB_ONE`], that the next bit is
+    /// a one. It always lies strictly between zero and one.
+    pub fn p (&self) -> u32 {
+        self.pr
+    }
+
+    /// Trains every component on the coded `bit` and predicts the next one.
+    pub fn update (&mut self, bit: bool) {
+        let err = ((bit as i32) << 12) - self.mixed as i32;
+        let weights = &mut self.weights[self.set * INPUTS..][..INPUTS];
+        for (w, &x) in weights.iter_mut().zip(self.inputs.iter()) {
+            *w = (*w + ((x * err * LEARNING_RATE) >> 13)).clamp(-MAX_WEIGHT, MAX_WEIGHT);
+        }
+        if let Some(sse) = self.sse.as_mut() {
+            sse.hits.update(bit);
+            sse.order1.update(bit);
+            let hit = (self.pr >= PROB_ONE as u32 / 2) == bit;
+            sse.history = ((sse.history << 1) | hit as u32) & ((1 << HIT_BITS) - 1);
+        }
+        for i in 0..CONTEXTS {
+            counter_update(&mut self.tables[i][self.slots[i]], bit, &self.reciprocals);
+        }
+        self.matcher.update(bit);
+
+        self.c0 = (self.c0 << 1) | bit as u32;
+        self.bits += 1;
+        if self.bits == 8 {
+            let byte = (self.c0 & 0xff) as u8;
+            self.history = (self.history << 8) | byte as u64;
+            self.word = if byte.is_ascii_alphabetic() {
+                (self.word ^ byte.to_ascii_lowercase() as u64).wrapping_mul(0x100_0000_01b3)
+            } else {
+                0
+            };
+            self.c0 = 1;
+            self.bits = 0;
+            self.next_byte();
+        } else if self.bits == 4 {
+            self.next_nibble();
+        }
+        self.predict();
+    }
+
+    // Hashes the contexts for the next byte.
+    fn next_byte (&mut self) {
+        let h = self.history;
+        let last = h as u8;
+        self.class = if last.is_ascii_alphabetic() { 0 } else if last == b' ' { 1 } else if last.is_ascii_digit() { 2 } else { 3 };
+        let contexts = [
+            0,
+            h & 0xff,
+            h & 0xffff,
+            h & 0xff_ffff,
+            h & 0xffff_ffff,
+            h & 0xff_ffff_ffff,
+            h & 0xffff_ffff_ffff,
+            if self.word == 0 { h & 0xff } else { self.word },
+            (h >> 8) & 0xffff
+        ];
+        for (i, &c) in contexts.iter().enumerate() {
+            self.hashes[i] = hash(c, i);
+        }
+        self.next_nibble();
+    }
+
+    // Each context gets a block of 16 counters per nibble, indexed by the
+    // bits of the nibble seen so far, so a byte touches two blocks.
+    fn next_nibble (&mut self) {
+        for ((base, &hash), &bits) in self.bases.iter_mut().zip(self.hashes.iter()).zip(TABLE_BITS.iter()) {
+            let h = hash.wrapping_add(self.c0.wrapping_mul(0x2f0b_7a49)).wrapping_mul(0x9e37_79b1);
+            *base = ((h >> (32 - bits)) & !15) as usize;
+        }
+    }
+
+    fn predict (&mut self) {
+        let nibble = (1 << (self.bits & 3)) | (self.c0 & ((1 << (self.bits & 3)) - 1));
+        for i in 0..CONTEXTS {
+            self.slots[i] = self.bases[i] + nibble as usize;
+            let c = self.tables[i][self.slots[i]];
+            self.inputs[i] = self.stretch.get(counter_p(c));
+            self.inputs[CONTEXTS + i] = self.inputs[i] * (YOUNG - (c & 1023).min(YOUNG)) as i32 / YOUNG as i32;
+        }
+        self.inputs[2 * CONTEXTS] = self.stretch.get(self.matcher.p());
+        self.inputs[2 * CONTEXTS + 1] = match self.matcher.expected() {
+            Some(bit) => (if bit { 64 } else { -64 }) * self.matcher.length().min(32) as i32,
+            None => 0
+        };
+        self.inputs[2 * CONTEXTS + 2] = 256;
+
+        let matching = self.matcher.expected().is_some() as usize;
+        self.set = (2 * self.class + matching) * 256 + self.c0 as usize;
+        let weights = &self.weights[self.set * INPUTS..][..INPUTS];
+        let dot: i64 = weights.iter().zip(self.inputs.iter()).map(|(&w, &x)| w as i64 * x as i64).sum();
+        let d = (dot >> 16).clamp(-2047, 2047) as i32;
+        self.mixed = squash(d).clamp(1, PROB_ONE as i32 - 1) as u32;
+        self.pr = match self.sse.as_mut() {
+            Some(sse) => {
+                let p1 = sse.hits.refine(self.mixed, (sse.history << 8 | self.c0) as usize);
+                let p2 = sse.order1.refine(self.mixed, ((self.history as usize & 0xff) << 8) | self.c0 as usize);
+                (self.mixed + p1 + 2 * p2 + 2) >> 2
+            }
+            None => self.mixed
+        };
+    }
+
+    /// Encodes `byte` and trains the model on it.
+    pub fn encode (&mut self, byte: u8, encoder: &mut BinaryEncoder, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) & 1 != 0;
+            encoder.encode_with_probability((PROB_ONE as u32 - self.pr) as u16, bit, bits_out)?;
+            self.update(bit);
+        }
+        Ok(())
+    }
+
+    /// Decodes the next byte and trains the model on it.
+    pub fn decode (&mut self, decoder: &mut BinaryDecoder, bits_in: &mut impl io::Next) -> Result<u8, std::io::Error> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            let bit = decoder.decode_with_probability((PROB_ONE as u32 - self.pr) as u16, bits_in)?;
+            self.update(bit);
+            byte = (byte << 1) | bit as u8;
+        }
+        Ok(byte)
+    }
+}
+
+impl Default for CmModel {
+    fn default () -> Self {
+        CmModel::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    fn round_trip (message: &[u8]) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = BinaryEncoder::new();
+        let mut model = CmModel::new();
+        for &b in message.iter() {
+            model.encode(b, &mut encoder, &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = BinaryDecoder::new();
+        let mut model = CmModel::new();
+        decoder.begin(&mut bits_in).unwrap();
+        for &b in message.iter() {
+            assert_eq!(model.decode(&mut decoder, &mut bits_in).unwrap(), b);
+        }
+        coded
+    }
+
+    #[test]
+    fn test_squash_stretch () {
+        let stretch = Stretch::new();
+        assert_eq!(squash(0), 2047);
+        for d in (-1500..=1500).step_by(7) {
+            assert!((stretch.get(squash(d) as u32) - d).abs() <= 64);
+        }
+        for p in 1..4095 {
+            assert!(stretch.get(p) <= stretch.get(p + 1));
+        }
+    }
+    #[test]
+    fn test_cm_round_trip_1 () {
+        let text = b"It was the best of times, it was the worst of times, it was the age of wisdom. ".repeat(50);
+        let coded = round_trip(&text);
+        assert!(coded.len() < text.len() / 30);
+    }
+    #[test]
+    fn test_cm_round_trip_2 () {
+        let message: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let coded = round_trip(&message);
+        assert!(coded.len() < message.len() * 102 / 100);
+    }
+    #[test]
+    fn test_weights_bounded () {
+        // Weights at the limit, pushed further by every misprediction.
+        let mut model = CmModel::new();
+        model.weights.fill(MAX_WEIGHT);
+        for i in 0..2000 {
+            model.update(i % 3 == 0);
+        }
+        assert!(model.weights.iter().all(|w| w.abs() <= MAX_WEIGHT));
+    }
+}
@@ -1,66 +1,206 @@
+//! The CACM87 arithmetic coder.
+//!
+//! [`ArithmeticEncoder`] and [`ArithmeticDecoder`] carry a [`Model`], by
+//! default an [`AdaptiveModel`] over an alphabet of `size` symbols. Symbols
+//! are coded one at a time, and both sides must call `discover` with the same
+//! symbols in the same order to keep their models in sync.
+//!
+//! ```
+//! use simple_arithmetic_coding::{ArithmeticDecoder, ArithmeticEncoder, Input, Output};
+//!
+//! let message = [3usize, 1, 4, 1, 5, 9, 2, 6];
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = ArithmeticEncoder::new(10);
+//! for &s in message.iter() {
+//!     encoder.encode(s, &mut bits_out)?;
+//!     encoder.discover(s);
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = ArithmeticDecoder::new(10);
+//! decoder.begin(&mut bits_in)?;
+//! for &s in message.iter() {
+//!     let d = decoder.decode(&mut bits_in)?;
+//!     assert_eq!(d, s);
+//!     decoder.discover(d);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
 use num_traits::PrimInt;
-use crate::{fenwick::FenwickTree, io};
 
-type Value = u32;
+use crate::{error::Error, io, model::{AdaptiveModel, Model}};
+
+/// An unsigned register type the coder can run on.
+///
+/// The code range uses half of the register's bits, so that the product of
+/// a range and a cumulative frequency always fits. The quarter points of the
+/// range and the largest usable model total follow from that.
+pub trait CodeValue: PrimInt {
+    /// Number of bits in the code range.
+    const CODE_BITS: u32;
+    /// The largest value of the code range.
+    const TOP: Self;
+    /// The quarter points of the code range.
+    const FIRST_QTR: Self;
+    const HALF: Self;
+    const THIRD_QTR: Self;
+    /// The largest model total the coder can work with.
+    const MAX_FREQUENCY: u32;
+}
+
+macro_rules! code_value {
+    ($t:ty, $bits:expr) => {
+        impl CodeValue for $t {
+            const CODE_BITS: u32 = $bits;
+            const TOP: $t = (1 << $bits) - 1;
+            const FIRST_QTR: $t = Self::TOP / 4 + 1;
+            const HALF: $t = 2 * Self::FIRST_QTR;
+            const THIRD_QTR: $t = 3 * Self::FIRST_QTR;
+            const MAX_FREQUENCY: u32 = (1 << ($bits - 2)) - 1;
+        }
+    };
+}
 
-const VALB: u8 = 16;
-const TOP: Value = (1 << VALB) - 1;
-const FIRST_QTR: Value = TOP / 4 + 1;
-const HALF: Value = 2 * FIRST_QTR;
-const THIRD_QTR: Value = 3 * FIRST_QTR;
+code_value!(u16, 8);
+code_value!(u32, 16);
+code_value!(u64, 32);
 
-const MAX_FREQUENCY: Value = (u16::max_value()>>2) as Value;
+/// The largest model total the default (`u32`) coder can work with.
+pub const MAX_FREQUENCY: u32 = <u32 as CodeValue>::MAX_FREQUENCY;
+
+fn widen<W: CodeValue>(v: u32) -> W {
+    W::from(v).unwrap()
+}
+
+fn bit<W: CodeValue>(b: bool) -> W {
+    if b { W::one() } else { W::zero() }
+}
+
+// Checks that `cdf` starts at zero, never decreases and totals at most
+// `W::MAX_FREQUENCY`.
+fn check_cdf<W: CodeValue>(cdf: &[u32]) -> Result<(), std::io::Error> {
+    let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid CDF: {what}"));
+    if cdf.len() < 2 || cdf[0] != 0 {
+        return Err(invalid("it must start at zero and cover a symbol"));
+    }
+    if cdf.windows(2).any(|w| w[0] > w[1]) {
+        return Err(invalid("it decreases"));
+    }
+    if cdf[cdf.len()-1] == 0 || cdf[cdf.len()-1] > W::MAX_FREQUENCY {
+        return Err(invalid("its total is out of range"));
+    }
+    Ok(())
+}
 
-pub struct ArithmeticEncoder<Value>
-where Value: PrimInt
+/// Encodes symbols drawn from a [`Model`] into a bit sink, using registers
+/// of type `W`.
+pub struct ArithmeticEncoder<M = AdaptiveModel, W = u32>
+where M: Model, W: CodeValue
 {
-    model: FenwickTree<Value>,
-    low: Value,
-    high: Value,
-    pending: Value
+    model: M,
+    low: W,
+    high: W,
+    pending: u64,
+    // Bits written so far, for aligning flushes to bytes.
+    written: u64
 }
 
-impl ArithmeticEncoder<Value>
+impl ArithmeticEncoder
 {
+    /// Creates an encoder with an [`AdaptiveModel`] of `size` symbols.
     pub fn new (size: usize) -> Self {
+        ArithmeticEncoder::with_model(AdaptiveModel::new(size))
+    }
+}
+
+impl<M> ArithmeticEncoder<M>
+where M: Model
+{
+    /// Creates an encoder that codes symbols with `model`.
+    pub fn with_model (model: M) -> Self {
+        ArithmeticEncoder::with_width(model)
+    }
+}
+
+impl<M, W> ArithmeticEncoder<M, W>
+where M: Model, W: CodeValue
+{
+    /// Creates an encoder that codes symbols with `model` on registers of
+    /// type `W`, e.g. `ArithmeticEncoder::<_, u64>::with_width(model)`.
+    pub fn with_width (model: M) -> Self {
         ArithmeticEncoder {
-            model: FenwickTree::<Value>::new(size, Some(1)),
-            low: 0,
-            high: TOP,
-            pending: 0
+            model,
+            low: W::zero(),
+            high: W::TOP,
+            pending: 0,
+            written: 0
         }
     }
 
-    pub fn discover(&mut self, s: usize) -> () {
-        if self.model.total() == MAX_FREQUENCY {
-            self.model.scale(2);
-        }
-        self.model.add(s, 1);
+    /// Returns the model.
+    pub fn model (&self) -> &M {
+        &self.model
     }
 
+    /// Records an occurrence of `s` in the model.
+    pub fn discover(&mut self, s: usize) {
+        self.model.update(s);
+    }
+
+    /// Encodes `s` with the current model.
     pub fn encode(&mut self, s: usize, bits_out: &mut impl io::Push) -> Result<(), std::io::Error>
     {
-        let lower = self.model.sum(s-1);
-        let upper = self.model.sum(s);
+        let (lower, upper) = self.model.cum_freq(s);
         let denom = self.model.total();
+        self.encode_interval(lower, upper, denom, bits_out)
+    }
 
-        let range = (self.high - self.low) + 1;
-        self.high = self.low + (range * upper) / denom - 1;
-        self.low  = self.low + (range * lower) / denom;
+    /// Encodes `s` with an externally supplied distribution instead of the
+    /// model. `cdf` holds one more entry than there are symbols: `cdf[0]` is
+    /// zero, symbol `s` owns `cdf[s]..cdf[s+1]`, and the last entry is the
+    /// total, at most `W::MAX_FREQUENCY`. See
+    /// [`quantize_cdf`](crate::model::quantize_cdf) for building one from
+    /// probabilities. A `cdf` that is not laid out so, or gives `s` no
+    /// frequency, is rejected with [`std::io::ErrorKind::InvalidInput`].
+    pub fn encode_with_cdf(&mut self, s: usize, cdf: &[u32], bits_out: &mut impl io::Push) -> Result<(), std::io::Error>
+    {
+        check_cdf::<W>(cdf)?;
+        if s + 1 >= cdf.len() || cdf[s] == cdf[s+1] {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("symbol {s} has no frequency in the CDF")));
+        }
+        self.encode_interval(cdf[s], cdf[s+1], cdf[cdf.len()-1], bits_out)
+    }
+
+    /// Narrows the interval to `[lower, upper)` out of `denom`, for models
+    /// that compute their own frequencies, e.g. ones that code a symbol as a
+    /// sequence of escapes. `denom` must be at most `W::MAX_FREQUENCY`.
+    pub fn encode_interval(&mut self, lower: u32, upper: u32, denom: u32, bits_out: &mut impl io::Push) -> Result<(), std::io::Error>
+    {
+        assert!(denom <= W::MAX_FREQUENCY);
+        let (lower, upper, denom): (W, W, W) = (widen(lower), widen(upper), widen(denom));
+
+        let range = (self.high - self.low) + W::one();
+        self.high = self.low + (range * upper) / denom - W::one();
+        self.low = self.low + (range * lower) / denom;
         loop {
-            if self.high < HALF {
+            if self.high < W::HALF {
                 self.write_bit_plus_pending(false, bits_out)?;
-            } else if self.low >= HALF {
+            } else if self.low >= W::HALF {
                 self.write_bit_plus_pending(true, bits_out)?;
-            } else if (self.low >= FIRST_QTR) && (self.high < THIRD_QTR) {
+            } else if (self.low >= W::FIRST_QTR) && (self.high < W::THIRD_QTR) {
                 self.pending += 1;
-                self.low -= FIRST_QTR;
-                self.high -= FIRST_QTR;
+                self.low = self.low - W::FIRST_QTR;
+                self.high = self.high - W::FIRST_QTR;
             } else {
                 break;
             }
-            self.low = (self.low << 1) & TOP;
-            self.high = (self.high << 1) & TOP | 1;
+            self.low = (self.low << 1) & W::TOP;
+            self.high = (self.high << 1) & W::TOP | W::one();
         }
         Ok(())
     }
@@ -71,100 +211,817 @@ impl ArithmeticEncoder<Value>
         for _ in 0..self.pending {
             bits_out.push_bit(!bit)?;
         }
+        self.written += 1 + self.pending;
         self.pending = 0;
         Ok(())
     }
 
+    /// Writes the bits that disambiguate the final interval, padded so the
+    /// decoder, which reads a register's worth of bits ahead, finds all it
+    /// reads among them and never reads past the stream. The sink still has
+    /// to be flushed afterwards.
     pub fn finish(&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
         self.pending += 1;
-        if self.low < FIRST_QTR {
+        if self.low < W::FIRST_QTR {
             self.write_bit_plus_pending(false, bits_out)?;
         } else {
             self.write_bit_plus_pending(true, bits_out)?;
         }
+        for _ in 2..W::CODE_BITS {
+            bits_out.push_bit(false)?;
+        }
+        self.written += W::CODE_BITS as u64 - 2;
+        Ok(())
+    }
+
+    /// Writes out every bit the symbols encoded so far depend on, followed
+    /// by [`SYNC_MARKER`] on a byte boundary, then starts a fresh interval
+    /// with the model as it is. Once the sink is flushed, a decoder can
+    /// decode all symbols up to here from the bytes written so far and
+    /// resynchronize with [`ArithmeticDecoder::sync`]. Byte boundaries are
+    /// counted from the first bit this encoder wrote. A flush costs what
+    /// [`ArithmeticEncoder::finish`] does and the marker, plus up to seven
+    /// bits of padding.
+    pub fn flush (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        self.finish(bits_out)?;
+        let end = self.written.next_multiple_of(8);
+        for _ in self.written..end {
+            bits_out.push_bit(false)?;
+        }
+        for byte in SYNC_MARKER {
+            bits_out.push_byte(byte)?;
+        }
+        self.written = end + 8 * SYNC_MARKER.len() as u64;
+        self.low = W::zero();
+        self.high = W::TOP;
         Ok(())
     }
 }
 
-pub struct ArithmeticDecoder<Value>
-where Value: PrimInt
+/// The bytes [`ArithmeticEncoder::flush`] ends with.
+pub const SYNC_MARKER: [u8; 2] = [0x00, 0xff];
+
+/// Decodes symbols produced by an [`ArithmeticEncoder`] with an identical
+/// model and register width.
+pub struct ArithmeticDecoder<M = AdaptiveModel, W = u32>
+where M: Model, W: CodeValue
 {
-    model: FenwickTree<Value>,
-    low: Value,
-    high: Value,
-    value: Value,
+    model: M,
+    low: W,
+    high: W,
+    value: W,
+    // Zeroes read in place of bits past the end of the stream.
+    overrun: u32,
+    // Bits read so far, for finding where a flush ends.
+    consumed: u64
 }
 
-impl ArithmeticDecoder<Value>
+impl ArithmeticDecoder
 {
+    /// Creates a decoder with an [`AdaptiveModel`] of `size` symbols.
     pub fn new (size: usize) -> Self {
+        ArithmeticDecoder::with_model(AdaptiveModel::new(size))
+    }
+}
+
+impl<M> ArithmeticDecoder<M>
+where M: Model
+{
+    /// Creates a decoder that codes symbols with `model`.
+    pub fn with_model (model: M) -> Self {
+        ArithmeticDecoder::with_width(model)
+    }
+}
+
+impl<M, W> ArithmeticDecoder<M, W>
+where M: Model, W: CodeValue
+{
+    /// Creates a decoder that codes symbols with `model` on registers of
+    /// type `W`, mirroring [`ArithmeticEncoder::with_width`].
+    pub fn with_width (model: M) -> Self {
         ArithmeticDecoder {
-            model: FenwickTree::<Value>::new(size, Some(1)),
-            low: 0,
-            high: TOP,
-            value: 0
+            model,
+            low: W::zero(),
+            high: W::TOP,
+            value: W::zero(),
+            overrun: 0,
+            consumed: 0
         }
     }
 
-    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), std::io::Error>
+    /// Returns the model.
+    pub fn model (&self) -> &M {
+        &self.model
+    }
+
+    /// Reads the first bits of the stream. Must be called once before `decode`.
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error>
     {
-        for _ in 0..VALB {
-            if let Some(b) = bits_in.next_bit()? {
-                self.value = (self.value << 1) | b as Value;
-            } else {
-                break;
-            }
+        for _ in 0..W::CODE_BITS {
+            self.value = (self.value << 1) | bit(self.next_bit(bits_in)?);
         }
         Ok(())
     }
 
-    pub fn discover(&mut self, s: usize) -> () {
-        if self.model.total() == MAX_FREQUENCY {
-            self.model.scale(2);
+    // The next bit of the stream. Streams coded before the encoder padded
+    // its final bits stop short of the bits the decoder reads ahead, so
+    // these are zeroes past the end, but never more than a register's
+    // worth.
+    fn next_bit (&mut self, bits_in: &mut impl io::Next) -> Result<bool, Error> {
+        self.consumed += 1;
+        if let Some(b) = bits_in.next_bit()? {
+            return Ok(b);
+        }
+        self.overrun += 1;
+        if self.overrun > W::CODE_BITS {
+            return Err(Error::Truncated);
         }
-        self.model.add(s, 1);
+        Ok(false)
     }
 
-    pub fn decode(&mut self, bits_in: &mut impl io::Next) -> Result<usize, std::io::Error>
+    /// Skips the rest of a flush written by [`ArithmeticEncoder::flush`],
+    /// once every symbol before it has been decoded, without reading past
+    /// it. Fails with [`Error::Corrupt`] if the stream holds no flush here.
+    /// Call `begin` again before decoding the symbols that follow.
+    pub fn sync (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error> {
+        // The register already holds the padding up to here.
+        let mut mismatch = false;
+        while self.consumed % 8 != 0 {
+            mismatch |= self.next_bit(bits_in)?;
+        }
+        for byte in SYNC_MARKER {
+            let mut read = 0;
+            for _ in 0..8 {
+                read = (read << 1) | self.next_bit(bits_in)? as u8;
+            }
+            mismatch |= read != byte;
+        }
+        if self.overrun > 0 {
+            return Err(Error::Truncated);
+        }
+        if mismatch {
+            return Err(Error::corrupt("no flush marker where one was expected"));
+        }
+        self.low = W::zero();
+        self.high = W::TOP;
+        self.value = W::zero();
+        Ok(())
+    }
+
+    /// Records an occurrence of `s` in the model, mirroring
+    /// [`ArithmeticEncoder::discover`].
+    pub fn discover(&mut self, s: usize) {
+        self.model.update(s);
+    }
+
+    /// Decodes the next symbol with the current model. Fails with
+    /// [`Error::Corrupt`] if the model has no symbol for the coded count.
+    pub fn decode(&mut self, bits_in: &mut impl io::Next) -> Result<usize, Error>
     {
-        let range = (self.high - self.low) + 1;
         let denom = self.model.total();
-        let cum = (((self.value - self.low) + 1) * denom - 1) / range;
-        let s = self.model.upper(cum);
-        let upper = self.model.sum(s);
-        let lower = self.model.sum(s-1);
+        let cum = self.decode_count(denom)?;
+        let s = self.model.symbol(cum);
+        if s >= self.model.size() {
+            return Err(Error::corrupt(format!("symbol {s} is outside the alphabet")));
+        }
+        let (lower, upper) = self.model.cum_freq(s);
+        self.decode_interval(lower, upper, denom, bits_in)?;
+        Ok(s)
+    }
+
+    /// Decodes the next symbol with an externally supplied distribution,
+    /// laid out as for [`ArithmeticEncoder::encode_with_cdf`]. A `cdf` that
+    /// is not is rejected with an [`Error::Io`] of kind
+    /// [`std::io::ErrorKind::InvalidInput`].
+    pub fn decode_with_cdf(&mut self, cdf: &[u32], bits_in: &mut impl io::Next) -> Result<usize, Error>
+    {
+        check_cdf::<W>(cdf)?;
+        let cum = self.decode_count(cdf[cdf.len()-1])?;
+        let s = cdf.partition_point(|&c| c <= cum) - 1;
+        self.decode_interval(cdf[s], cdf[s+1], cdf[cdf.len()-1], bits_in)?;
+        Ok(s)
+    }
 
-        self.high = self.low + (range * upper) / denom - 1;
-        self.low  = self.low + (range * lower) / denom;
+    /// Returns the cumulative count, out of `denom`, that the next coded
+    /// interval contains. It must be followed by [`decode_interval`] with
+    /// the interval the encoder used.
+    ///
+    /// [`decode_interval`]: ArithmeticDecoder::decode_interval
+    pub fn decode_count(&self, denom: u32) -> Result<u32, Error> {
+        if denom == 0 || denom > W::MAX_FREQUENCY {
+            return Err(Error::corrupt(format!("model total {denom} is out of range")));
+        }
+        if self.value < self.low || self.value > self.high {
+            return Err(Error::corrupt("code value outside the coding interval"));
+        }
+        let range = (self.high - self.low) + W::one();
+        let cum = (((self.value - self.low) + W::one()) * widen(denom) - W::one()) / range;
+        Ok(cum.to_u32().unwrap())
+    }
+
+    /// Consumes the interval `[lower, upper)` out of `denom`, mirroring
+    /// [`ArithmeticEncoder::encode_interval`]. Fails with
+    /// [`Error::Corrupt`] if the interval is empty, does not fit in
+    /// `denom` or does not contain the coded value.
+    pub fn decode_interval(&mut self, lower: u32, upper: u32, denom: u32, bits_in: &mut impl io::Next) -> Result<(), Error>
+    {
+        if lower >= upper || upper > denom || denom > W::MAX_FREQUENCY {
+            return Err(Error::corrupt(format!("interval {lower}..{upper} out of {denom} is invalid")));
+        }
+        let (lower, upper, denom): (W, W, W) = (widen(lower), widen(upper), widen(denom));
+        let range = (self.high - self.low) + W::one();
+        self.high = self.low + (range * upper) / denom - W::one();
+        self.low = self.low + (range * lower) / denom;
+        if self.value < self.low || self.value > self.high {
+            return Err(Error::corrupt("code value outside the decoded interval"));
+        }
 
         loop {
-            if self.high < HALF {
+            if self.high < W::HALF {
                 // Do nothing
-            } else if self.low >= HALF {
-                self.value -= HALF;
-                self.low -= HALF;
-                self.high -= HALF;
-            } else if (self.low >= FIRST_QTR) && (self.high < THIRD_QTR) {
-                self.value -= FIRST_QTR;
-                self.low -= FIRST_QTR;
-                self.high -= FIRST_QTR;
+            } else if self.low >= W::HALF {
+                self.value = self.value - W::HALF;
+                self.low = self.low - W::HALF;
+                self.high = self.high - W::HALF;
+            } else if (self.low >= W::FIRST_QTR) && (self.high < W::THIRD_QTR) {
+                self.value = self.value - W::FIRST_QTR;
+                self.low = self.low - W::FIRST_QTR;
+                self.high = self.high - W::FIRST_QTR;
             } else {
                 break;
             }
-            self.low <<= 1;
-            self.high = (self.high << 1) | 1;
+            self.low = self.low << 1;
+            self.high = (self.high << 1) | W::one();
+            self.value = (self.value << 1) | bit(self.next_bit(bits_in)?);
+        }
+        Ok(())
+    }
+}
 
-            if let Some(b) = bits_in.next_bit()? {
-                self.value = (self.value << 1) | b as Value;
-            } else {
-                // input is "garbage" (zeroes)
-                self.value <<= 1;
+// The bijective coder runs on u64 registers, whose code range is 32 bits.
+const BIJECTIVE_BITS: u64 = <u64 as CodeValue>::CODE_BITS as u64;
+
+// A step of renormalization: a settled bit, with the number of pending
+// opposite bits that follow it, or one more pending bit.
+enum Shift {
+    Bit(bool, u64),
+    Follow
+}
+
+impl Shift {
+    fn offset (&self) -> u64 {
+        match self {
+            Shift::Bit(false, _) => 0,
+            Shift::Bit(true, _) => <u64 as CodeValue>::HALF,
+            Shift::Follow => <u64 as CodeValue>::FIRST_QTR
+        }
+    }
+}
+
+// The interval shared by the bijective encoder and decoder, and the points
+// claimed as the ends of shorter messages.
+//
+// Every byte string `c` stands for the point `0.c1` in binary. Every prefix
+// of a message claims, as its end, the first point of its interval, by
+// length and then by value, that no shorter prefix has claimed; the coded
+// message is the byte string of the point its whole interval claims. The
+// interval is `settled` bits, `pending` underflow bits and the registers.
+//
+// Points of one length are claimed in order of value, and the interval only
+// ever shrinks, so the claimed points of each length that are still inside
+// it are always the first few of that length. They are kept as the length,
+// the register position of the first one, where they are all whole
+// numbers, and how many there are.
+struct Claims {
+    low: u64,
+    high: u64,
+    settled: u64,
+    pending: u64,
+    // The position of the last one among the settled bits, or zero.
+    last_one: u64,
+    claimed: Vec<(u64, u64, u64)>
+}
+
+impl Claims {
+    fn new () -> Self {
+        Claims {
+            low: 0,
+            high: <u64 as CodeValue>::TOP,
+            settled: 0,
+            pending: 0,
+            last_one: 0,
+            claimed: Vec::new()
+        }
+    }
+
+    fn count (&self, len: u64) -> u64 {
+        self.claimed.iter().find(|c| c.0 == len).map_or(0, |c| c.2)
+    }
+
+    // Returns the length in bits and the register position of the first
+    // unclaimed point, and claims it.
+    fn claim (&mut self) -> (u64, u64) {
+        let (len, at) = self.first_free();
+        match self.claimed.iter_mut().find(|c| c.0 == len) {
+            Some(c) => c.2 += 1,
+            None => self.claimed.push((len, at, 1))
+        }
+        (len, at)
+    }
+
+    fn first_free (&self) -> (u64, u64) {
+        let half = <u64 as CodeValue>::HALF;
+        // Points shorter than the register scale can resolve, each the only
+        // one of its length: the start of the settled bits, their midpoint,
+        // and the start of the pending bits.
+        if self.pending == 0 && self.low == 0 && self.last_one % 8 == 1 && self.count(self.last_one) == 0 {
+            return (self.last_one, 0);
+        }
+        if self.settled % 8 == 0 && self.count(self.settled + 1) == 0 {
+            return (self.settled + 1, half);
+        }
+        let base = self.settled + self.pending;
+        if self.pending > 0 && self.low == 0 && base % 8 == 0 && self.count(base + 1) == 0 {
+            return (base + 1, 0);
+        }
+        // Then odd multiples of ever finer powers of two, of the lengths
+        // that end a whole number of bytes before their last one.
+        let mut m = 2 + (9 - (base + 2) % 8) % 8;
+        while m <= BIJECTIVE_BITS {
+            let step = 1 << (BIJECTIVE_BITS - m);
+            let at = ((self.low.div_ceil(step) | 1) + 2 * self.count(base + m)) * step;
+            if at <= self.high {
+                return (base + m, at);
             }
+            m += 8;
         }
-        Ok(s)
+        // Every step of the coder shrinks the interval, so the claimed points
+        // of a length never fill it for long.
+        unreachable!("no unclaimed point left in the interval")
+    }
+
+    fn narrow (&mut self, lower: u32, upper: u32, denom: u32) {
+        assert!(denom <= MAX_FREQUENCY && lower < upper && upper - lower < denom);
+        let range = self.high - self.low + 1;
+        self.high = self.low + range * upper as u64 / denom as u64 - 1;
+        self.low += range * lower as u64 / denom as u64;
+        let (low, high, base) = (self.low, self.high, self.settled + self.pending);
+        for (len, first, count) in self.claimed.iter_mut() {
+            if *count == 1 || *first > high {
+                *count = (*count == 1 && *first >= low && *first <= high) as u64;
+                continue;
+            }
+            // Two points of a length fit only when it is on the register scale.
+            let spacing = 2 << (BIJECTIVE_BITS + base - *len);
+            let skip = low.saturating_sub(*first).div_ceil(spacing);
+            let last = ((high - *first) / spacing).min(*count - 1);
+            *count = (last + 1).saturating_sub(skip);
+            *first += skip * spacing;
+        }
+        self.claimed.retain(|c| c.2 > 0);
+    }
+
+    fn shift (&mut self) -> Option<Shift> {
+        let shift = if self.high < <u64 as CodeValue>::HALF {
+            Shift::Bit(false, self.pending)
+        } else if self.low >= <u64 as CodeValue>::HALF {
+            Shift::Bit(true, self.pending)
+        } else if self.low >= <u64 as CodeValue>::FIRST_QTR && self.high < <u64 as CodeValue>::THIRD_QTR {
+            Shift::Follow
+        } else {
+            return None;
+        };
+        match shift {
+            Shift::Bit(bit, pending) => {
+                if bit {
+                    self.last_one = self.settled + 1;
+                } else if pending > 0 {
+                    self.last_one = self.settled + 1 + pending;
+                }
+                self.settled += 1 + pending;
+                self.pending = 0;
+            }
+            Shift::Follow => self.pending += 1
+        }
+        let offset = shift.offset();
+        self.low = (self.low - offset) << 1;
+        self.high = ((self.high - offset) << 1) | 1;
+        for (_, first, _) in self.claimed.iter_mut() {
+            *first = (*first - offset) << 1;
+        }
+        Some(shift)
     }
 }
 
+/// A CACM87 encoder whose output is bijective: every byte string is the
+/// code of exactly one message.
+///
+/// There is no EOF symbol and no padding. Every byte string `c` stands for
+/// the binary point `0.c1`, and every message, including each prefix of a
+/// longer one, owns the first point of its coding interval, by length and
+/// then by value, that none of its own prefixes owns. The encoder writes the
+/// point of the whole message; [`BijectiveDecoder`] decodes symbols until
+/// the point it reads is the one the message so far owns. Decoding never
+/// fails, and encoding what it returns gives back the same bytes.
+///
+/// The model must give every symbol some, but never all, of its total, which
+/// may be at most [`MAX_FREQUENCY`].
+pub struct BijectiveEncoder<M = AdaptiveModel>
+where M: Model
+{
+    model: M,
+    claims: Claims,
+    // The settled bits are written as they come, except for a final one and
+    // the zeroes after it, in case the message ends at that one.
+    held: Option<u64>
+}
 
+impl BijectiveEncoder
+{
+    /// Creates an encoder with an [`AdaptiveModel`] of `size` symbols.
+    pub fn new (size: usize) -> Self {
+        BijectiveEncoder::with_model(AdaptiveModel::new(size))
+    }
+}
 
+impl<M> BijectiveEncoder<M>
+where M: Model
+{
+    /// Creates an encoder that codes symbols with `model`.
+    pub fn with_model (model: M) -> Self {
+        BijectiveEncoder {
+            model,
+            claims: Claims::new(),
+            held: None
+        }
+    }
+
+    /// Returns the model.
+    pub fn model (&self) -> &M {
+        &self.model
+    }
 
+    /// Records an occurrence of `s` in the model.
+    pub fn discover(&mut self, s: usize) {
+        self.model.update(s);
+    }
+
+    /// Encodes `s` with the current model.
+    pub fn encode(&mut self, s: usize, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        let (lower, upper) = self.model.cum_freq(s);
+        let denom = self.model.total();
+        self.claims.claim();
+        self.claims.narrow(lower, upper, denom);
+        while let Some(shift) = self.claims.shift() {
+            if let Shift::Bit(bit, pending) = shift {
+                self.write_bit(bit, bits_out)?;
+                for _ in 0..pending {
+                    self.write_bit(!bit, bits_out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_bit (&mut self, bit: bool, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        if bit {
+            self.release(bits_out)?;
+            self.held = Some(0);
+        } else if let Some(zeroes) = self.held.as_mut() {
+            *zeroes += 1;
+        } else {
+            bits_out.push_bit(false)?;
+        }
+        Ok(())
+    }
+
+    fn release (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        if let Some(zeroes) = self.held.take() {
+            bits_out.push_bit(true)?;
+            for _ in 0..zeroes {
+                bits_out.push_bit(false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the rest of the point that ends the message. The output is
+    /// then a whole number of bytes, so the sink adds no padding when
+    /// flushed.
+    pub fn finish(&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        let (len, at) = self.claims.first_free();
+        let pending = self.claims.pending;
+        if at == 0 && pending == 0 {
+            // The point is where the settled bits end: drop their last one.
+            self.held = None;
+            return Ok(());
+        }
+        if at == 0 {
+            self.write_bit(false, bits_out)?;
+            for _ in 1..pending {
+                self.write_bit(true, bits_out)?;
+            }
+        } else if at != <u64 as CodeValue>::HALF {
+            let top = at >= <u64 as CodeValue>::HALF;
+            self.write_bit(top, bits_out)?;
+            for _ in 0..pending {
+                self.write_bit(!top, bits_out)?;
+            }
+            // All bits but the first and the final one.
+            let m = len - self.claims.settled - pending;
+            for i in (BIJECTIVE_BITS - m + 1..BIJECTIVE_BITS - 1).rev() {
+                self.write_bit((at >> i) & 1 != 0, bits_out)?;
+            }
+        }
+        self.release(bits_out)
+    }
+}
+
+/// Decodes any byte string as the output of a [`BijectiveEncoder`] with an
+/// identical model.
+pub struct BijectiveDecoder<M = AdaptiveModel>
+where M: Model
+{
+    model: M,
+    claims: Claims,
+    value: u64,
+    read: u64,
+    // The length of the point the input stands for, once it has all been read.
+    end: Option<u64>,
+    done: bool
+}
+
+impl BijectiveDecoder
+{
+    /// Creates a decoder with an [`AdaptiveModel`] of `size` symbols.
+    pub fn new (size: usize) -> Self {
+        BijectiveDecoder::with_model(AdaptiveModel::new(size))
+    }
+}
+
+impl<M> BijectiveDecoder<M>
+where M: Model
+{
+    /// Creates a decoder that codes symbols with `model`.
+    pub fn with_model (model: M) -> Self {
+        BijectiveDecoder {
+            model,
+            claims: Claims::new(),
+            value: 0,
+            read: 0,
+            end: None,
+            done: false
+        }
+    }
+
+    /// Returns the model.
+    pub fn model (&self) -> &M {
+        &self.model
+    }
+
+    /// Records an occurrence of `s` in the model, mirroring
+    /// [`BijectiveEncoder::discover`].
+    pub fn discover(&mut self, s: usize) {
+        self.model.update(s);
+    }
+
+    // The input, followed by a one and then zeroes.
+    fn next_bit (&mut self, bits_in: &mut impl io::Next) -> Result<u64, Error> {
+        if self.end.is_some() {
+            return Ok(0);
+        }
+        match bits_in.next_bit()? {
+            Some(b) => {
+                self.read += 1;
+                Ok(b as u64)
+            }
+            None => {
+                self.end = Some(self.read + 1);
+                Ok(1)
+            }
+        }
+    }
+
+    /// Reads the first bits of the stream. Must be called once before `decode`.
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error> {
+        for _ in 0..BIJECTIVE_BITS {
+            self.value = (self.value << 1) | self.next_bit(bits_in)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes the next symbol with the current model, or returns `None` at
+    /// the end of the message.
+    pub fn decode(&mut self, bits_in: &mut impl io::Next) -> Result<Option<usize>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+        let (len, at) = self.claims.claim();
+        if self.end == Some(len) && self.value == at {
+            self.done = true;
+            return Ok(None);
+        }
+        let denom = self.model.total();
+        let range = self.claims.high - self.claims.low + 1;
+        let cum = ((self.value - self.claims.low + 1) * denom as u64 - 1) / range;
+        let s = self.model.symbol(cum as u32);
+        let (lower, upper) = self.model.cum_freq(s);
+        self.claims.narrow(lower, upper, denom);
+        while let Some(shift) = self.claims.shift() {
+            self.value = ((self.value - shift.offset()) << 1) | self.next_bit(bits_in)?;
+        }
+        Ok(Some(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Next, Output};
+
+    fn round_trip<W: CodeValue> (message: &[usize], model: AdaptiveModel) -> usize {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        let mut encoder = ArithmeticEncoder::<_, W>::with_width(model.clone());
+        for &s in message.iter() {
+            encoder.encode(s, &mut bits_out).unwrap();
+            encoder.discover(s);
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::<_, W>::with_width(model);
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder.decode(&mut bits_in).unwrap(), s);
+            decoder.discover(s);
+        }
+        coded.len()
+    }
+
+    fn encode_bijective (message: &[usize], size: usize) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        let mut encoder = BijectiveEncoder::new(size);
+        for &s in message.iter() {
+            encoder.encode(s, &mut bits_out).unwrap();
+            encoder.discover(s);
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        coded
+    }
+
+    fn decode_bijective (coded: &[u8], size: usize) -> Vec<usize> {
+        let mut bits_in = Input::new(coded).unwrap();
+        let mut decoder = BijectiveDecoder::new(size);
+        decoder.begin(&mut bits_in).unwrap();
+        let mut message = Vec::new();
+        while let Some(s) = decoder.decode(&mut bits_in).unwrap() {
+            decoder.discover(s);
+            message.push(s);
+        }
+        message
+    }
+
+    // Every string of up to two bytes.
+    fn short_strings () -> impl Iterator<Item = Vec<u8>> {
+        std::iter::once(vec![])
+            .chain((0..=255).map(|a| vec![a]))
+            .chain((0..=0xffff_u32).map(|ab| vec![(ab >> 8) as u8, ab as u8]))
+    }
+
+    fn skewed (n: usize) -> Vec<usize> {
+        (0..n).map(|i| if i % 1000 == 999 { 1 + i % 3 } else { 0 }).collect()
+    }
+
+    #[test]
+    fn test_width_u16 () {
+        let limit = <u16 as CodeValue>::MAX_FREQUENCY;
+        round_trip::<u16>(&skewed(5000), AdaptiveModel::with_limit(4, limit));
+    }
+    #[test]
+    fn test_width_u32 () {
+        round_trip::<u32>(&skewed(5000), AdaptiveModel::new(4));
+    }
+    #[test]
+    fn test_width_u64 () {
+        let limit = <u64 as CodeValue>::MAX_FREQUENCY;
+        let message = skewed(200000);
+        let narrow = round_trip::<u32>(&message, AdaptiveModel::new(4));
+        let wide = round_trip::<u64>(&message, AdaptiveModel::with_limit(4, limit));
+        assert!(wide < narrow);
+    }
+    #[test]
+    fn test_bijective_decode_encode () {
+        for message in short_strings() {
+            let message: Vec<usize> = message.iter().map(|&b| b as usize).collect();
+            assert_eq!(decode_bijective(&encode_bijective(&message, 256), 256), message);
+        }
+        let message = skewed(20000);
+        let coded = encode_bijective(&message, 4);
+        assert_eq!(decode_bijective(&coded, 4), message);
+        // Costs no more than the usual end of stream.
+        assert!(coded.len() <= round_trip::<u32>(&message, AdaptiveModel::new(4)));
+    }
+    #[test]
+    fn test_bijective_encode_decode () {
+        for coded in short_strings() {
+            assert_eq!(encode_bijective(&decode_bijective(&coded, 256), 256), coded);
+            assert_eq!(encode_bijective(&decode_bijective(&coded, 2), 2), coded);
+        }
+    }
+    #[test]
+    fn test_bijective_random () {
+        // Longer strings than `short_strings` reach finer claimed points.
+        let mut state = 1u32;
+        for len in (0..3000).step_by(97) {
+            let coded: Vec<u8> = (0..len).map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            }).collect();
+            for size in [2, 3, 256] {
+                assert_eq!(encode_bijective(&decode_bijective(&coded, size), size), coded);
+            }
+            let skewed: Vec<u8> = coded.iter().map(|&b| b & b >> 1 & b >> 2).collect();
+            assert_eq!(encode_bijective(&decode_bijective(&skewed, 256), 256), skewed);
+        }
+    }
+    #[test]
+    fn test_corrupt_intervals () {
+        let mut bits_in = Input::new(&[0x55u8; 4][..]).unwrap();
+        let mut decoder = ArithmeticDecoder::new(4);
+        decoder.begin(&mut bits_in).unwrap();
+        assert!(matches!(decoder.decode_count(0), Err(Error::Corrupt(_))));
+        assert!(matches!(decoder.decode_count(MAX_FREQUENCY + 1), Err(Error::Corrupt(_))));
+        assert!(matches!(decoder.decode_interval(3, 3, 10, &mut bits_in), Err(Error::Corrupt(_))));
+        assert!(matches!(decoder.decode_interval(3, 11, 10, &mut bits_in), Err(Error::Corrupt(_))));
+        // The value 0x5555 is in the first third of the range, not the last.
+        assert!(matches!(decoder.decode_interval(2, 3, 3, &mut bits_in), Err(Error::Corrupt(_))));
+    }
+    fn sync_flush<W: CodeValue> () {
+        let messages: [&[usize]; 3] = [&[3, 1, 4, 1, 5], &[], &[9, 2, 6, 5, 3, 5, 8, 9, 7, 9]];
+        let mut encoder = ArithmeticEncoder::<_, W>::with_width(AdaptiveModel::new(10));
+        // Each message goes out on its own, as if sent as soon as it is flushed.
+        let mut sent: Vec<Vec<u8>> = Vec::new();
+        for message in messages {
+            let mut bits_out = Output::new(Vec::new(), 64);
+            for &s in message {
+                encoder.encode(s, &mut bits_out).unwrap();
+                encoder.discover(s);
+            }
+            encoder.flush(&mut bits_out).unwrap();
+            let bytes = bits_out.into_inner().unwrap();
+            assert_eq!(bytes[bytes.len() - 2..], SYNC_MARKER);
+            sent.push(bytes);
+        }
+
+        let mut decoder = ArithmeticDecoder::<_, W>::with_width(AdaptiveModel::new(10));
+        for (message, bytes) in messages.iter().zip(&sent) {
+            // Only the bytes received so far; `sync` fails if the decoder
+            // had to read past them.
+            let mut bits_in = Input::new(bytes.as_slice()).unwrap();
+            decoder.begin(&mut bits_in).unwrap();
+            for &s in message.iter() {
+                let d = decoder.decode(&mut bits_in).unwrap();
+                assert_eq!(d, s);
+                decoder.discover(d);
+            }
+            decoder.sync(&mut bits_in).unwrap();
+            assert!(bits_in.next_bit().unwrap().is_none());
+        }
+    }
+    #[test]
+    fn test_sync_flush () {
+        sync_flush::<u32>();
+        sync_flush::<u64>();
+    }
+    #[test]
+    fn test_sync_wrong_place () {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        let mut encoder = ArithmeticEncoder::new(4);
+        for s in [1, 2, 3, 0, 1] {
+            encoder.encode(s, &mut bits_out).unwrap();
+            encoder.discover(s);
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::new(4);
+        decoder.begin(&mut bits_in).unwrap();
+        let s = decoder.decode(&mut bits_in).unwrap();
+        decoder.discover(s);
+        assert!(decoder.sync(&mut bits_in).is_err());
+    }
+    #[test]
+    fn test_width_constants () {
+        assert_eq!(<u32 as CodeValue>::TOP, 0xffff);
+        assert_eq!(<u32 as CodeValue>::HALF, 0x8000);
+        assert_eq!(<u64 as CodeValue>::THIRD_QTR, 0xc000_0000);
+        assert_eq!(MAX_FREQUENCY, (1 << 14) - 1);
+    }
+}
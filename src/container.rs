@@ -0,0 +1,302 @@
+//! The header every stream written by [`encode_routine_with`] starts with.
+//!
+//! A stream opens with the four bytes of [`MAGIC`] and the format
+//! [`VERSION`], then names the coder [`Backend`], the [`ModelId`] and its
+//! parameter (the context order or the LZ77 window), and a byte of flags.
+//! The alphabet size, coder precision and rescale limit of the model follow,
+//! so a stream written with different model constants is rejected instead
+//! of decoding to garbage. Streams that are counted end the header with the
+//! input length. A CRC-32 of the header follows it, and streams flagged
+//! [`CHECKSUM`] end with a CRC-32 of the data they code, after the coded
+//! bits have been padded to a whole byte.
+//!
+//! | field | encoding |
+//! |---|---|
+//! | magic | 4 bytes |
+//! | version | byte |
+//! | backend | byte |
+//! | model | byte |
+//! | parameter | byte |
+//! | flags | byte |
+//! | alphabet size | varint |
+//! | precision in bits | byte |
+//! | rescale limit, 0 for none | varint |
+//! | input length, if [`COUNTED`] | varint |
+//! | CRC-32 of the above | 4 bytes, big-endian |
+//!
+//! ```
+//! use simple_arithmetic_coding::{encode_routine, Input};
+//! use simple_arithmetic_coding::container::{Header, ModelId};
+//!
+//! let mut coded = Vec::new();
+//! encode_routine(&mut &b"abracadabra"[..], &mut coded)?;
+//! let header = Header::read(&mut Input::new(coded.as_slice())?)?;
+//! assert_eq!(header.model, ModelId::Context);
+//! assert_eq!(header.alphabet, 257);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`encode_routine_with`]: crate::encode_routine_with
+
+use crate::{
+    binary, bwt,
+    checksum::{Checked, ChecksumError, Checksummed},
+    codec,
+    error::Error,
+    io::{self, Next, Push},
+    lz77, ppm, range, rans, Backend
+};
+
+/// The first bytes of every stream.
+pub const MAGIC: [u8; 4] = *b"SAC\x1a";
+/// The version of the format this crate writes and reads.
+pub const VERSION: u8 = 1;
+
+/// Set when the input length is in the header and no EOF symbol ends the
+/// stream.
+pub const COUNTED: u8 = 0x01;
+/// Set when a context-mixed or context-model stream refines its
+/// probabilities with SSE.
+pub const SSE: u8 = 0x02;
+/// Set when the stream ends with a CRC-32 of the data it codes.
+pub const CHECKSUM: u8 = 0x04;
+const FLAGS: u8 = COUNTED | SSE | CHECKSUM;
+
+/// The model, or the transform, the bytes of a stream are coded with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelId {
+    /// An adaptive model per context of `parameter` preceding bytes; order 0
+    /// is a single [`AdaptiveModel`](crate::AdaptiveModel).
+    Context,
+    /// [`ppm::PpmModel`] of order `parameter`.
+    Ppm,
+    /// An order-0 model split across the lanes of [`crate::interleave`],
+    /// which the stream records itself.
+    Interleaved,
+    /// [`crate::cm::CmModel`] driving the [`binary`] coder.
+    Mixing,
+    /// [`lz77`] with a window of `1 << parameter` bytes.
+    Lz77,
+    /// Block sorting with [`bwt`].
+    Bwt,
+}
+
+impl ModelId {
+    fn id (self) -> u8 {
+        match self {
+            ModelId::Context => 0,
+            ModelId::Ppm => 1,
+            ModelId::Interleaved => 2,
+            ModelId::Mixing => 3,
+            ModelId::Lz77 => 4,
+            ModelId::Bwt => 5,
+        }
+    }
+
+    fn from_id (id: u8) -> Option<ModelId> {
+        match id {
+            0 => Some(ModelId::Context),
+            1 => Some(ModelId::Ppm),
+            2 => Some(ModelId::Interleaved),
+            3 => Some(ModelId::Mixing),
+            4 => Some(ModelId::Lz77),
+            5 => Some(ModelId::Bwt),
+            _ => None,
+        }
+    }
+}
+
+/// The header of a stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The entropy coder. Context mixing, LZ77 and block sorting always
+    /// use their own and record [`Backend::Cacm87`].
+    pub backend: Backend,
+    /// The model.
+    pub model: ModelId,
+    /// The context order of [`ModelId::Context`] and [`ModelId::Ppm`], the
+    /// window bits of [`ModelId::Lz77`], and 0 otherwise.
+    pub parameter: u8,
+    /// [`COUNTED`], [`SSE`] and [`CHECKSUM`].
+    pub flags: u8,
+    /// The number of symbols the model codes.
+    pub alphabet: u32,
+    /// The bits of probability resolution of the coder.
+    pub precision: u32,
+    /// The total at which the model halves its counts, or 0 if it never
+    /// does.
+    pub rescale: u32,
+    /// The input length, exactly when the stream is [`COUNTED`].
+    pub length: Option<u64>,
+}
+
+impl Header {
+    /// Creates the header of a stream, with the alphabet size, precision and
+    /// rescale limit this version of the crate codes it with.
+    pub fn new (backend: Backend, model: ModelId, parameter: u8, flags: u8, length: Option<u64>) -> Self {
+        let mut header = Header { backend, model, parameter, flags, alphabet: 0, precision: 0, rescale: 0, length };
+        (header.alphabet, header.precision, header.rescale) = header.constants();
+        header
+    }
+
+    /// Returns whether the stream has the input length up front.
+    pub fn counted (&self) -> bool {
+        self.flags & COUNTED != 0
+    }
+
+    /// Returns whether the stream ends with a checksum of its data.
+    pub fn checksum (&self) -> bool {
+        self.flags & CHECKSUM != 0
+    }
+
+    // The alphabet size, precision and rescale limit of the model.
+    fn constants (&self) -> (u32, u32, u32) {
+        let alphabet = if self.counted() { 256 } else { 257 };
+        let precision = match self.backend {
+            Backend::Cacm87 => <u32 as codec::CodeValue>::CODE_BITS,
+            Backend::Range => range::MAX_FREQUENCY.trailing_zeros(),
+            Backend::Rans => rans::SCALE_BITS,
+        };
+        match self.model {
+            ModelId::Context | ModelId::Interleaved if self.backend == Backend::Rans => (alphabet, precision, 0),
+            ModelId::Context | ModelId::Interleaved => (alphabet, precision, codec::MAX_FREQUENCY),
+            ModelId::Ppm => (alphabet, precision, ppm::RESCALE),
+            ModelId::Mixing => (256, binary::PROB_BITS, 0),
+            ModelId::Lz77 => (lz77::LITERALS as u32, precision, codec::MAX_FREQUENCY),
+            ModelId::Bwt => (bwt::SYMBOLS as u32, precision, codec::MAX_FREQUENCY),
+        }
+    }
+
+    /// Writes the header and its checksum.
+    pub fn write (&self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        let mut fields = Checksummed::new(&mut *bits_out);
+        for byte in MAGIC {
+            fields.push_byte(byte)?;
+        }
+        fields.push_byte(VERSION)?;
+        fields.push_byte(self.backend.id())?;
+        fields.push_byte(self.model.id())?;
+        fields.push_byte(self.parameter)?;
+        fields.push_byte(self.flags)?;
+        fields.push_varint(self.alphabet as u64)?;
+        fields.push_byte(self.precision as u8)?;
+        fields.push_varint(self.rescale as u64)?;
+        if let Some(length) = self.length {
+            fields.push_varint(length)?;
+        }
+        let crc = fields.crc();
+        for byte in crc.to_be_bytes() {
+            bits_out.push_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a header, verifies its checksum and checks that this version
+    /// of the crate can decode the stream it starts.
+    pub fn read (bits_in: &mut impl io::Next) -> Result<Header, Error> {
+        let mut fields = Checksummed::new(&mut *bits_in);
+        for byte in MAGIC {
+            if next(&mut fields)? != byte {
+                return Err(Error::unsupported("not a compressed stream"));
+            }
+        }
+        // The layout of the rest may differ in other versions.
+        let version = next(&mut fields)?;
+        if version != VERSION {
+            return Err(Error::unsupported(format!("unsupported format version {version}, expected {VERSION}")));
+        }
+        let backend = next(&mut fields)?;
+        let model = next(&mut fields)?;
+        let parameter = next(&mut fields)?;
+        let flags = next(&mut fields)?;
+        let alphabet = fields.next_varint()?;
+        let precision = next(&mut fields)?;
+        let rescale = fields.next_varint()?;
+        let length = if flags & COUNTED != 0 { Some(fields.next_varint()?) } else { None };
+        let computed = fields.crc();
+        let mut stored = [0; 4];
+        for byte in stored.iter_mut() {
+            *byte = next(bits_in)?;
+        }
+        ChecksumError::check(Checked::Header, u32::from_be_bytes(stored), computed)?;
+
+        let backend = Backend::from_id(backend).ok_or_else(|| Error::unsupported(format!("unknown coder backend {backend}")))?;
+        let model = ModelId::from_id(model).ok_or_else(|| Error::unsupported(format!("unknown model {model}")))?;
+        if flags & !FLAGS != 0 {
+            return Err(Error::unsupported(format!("unknown flags {flags:#04x}")));
+        }
+        let header = Header::new(backend, model, parameter, flags, length);
+        if (alphabet, precision as u32, rescale) != (header.alphabet as u64, header.precision, header.rescale as u64) {
+            return Err(Error::unsupported(format!(
+                "unsupported model parameters: alphabet size {alphabet}, precision {precision}, rescale limit {rescale}; \
+                 this version uses {}, {} and {}", header.alphabet, header.precision, header.rescale)));
+        }
+        Ok(header)
+    }
+}
+
+fn next (bits_in: &mut impl io::Next) -> Result<u8, Error> {
+    bits_in.next_byte()?.ok_or(Error::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Input, Output};
+
+    fn write (header: &Header) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        header.write(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        coded
+    }
+
+    fn read (coded: &[u8]) -> Result<Header, Error> {
+        Header::read(&mut Input::new(coded).unwrap())
+    }
+
+    #[test]
+    fn test_header_round_trip () {
+        for header in [
+            Header::new(Backend::Range, ModelId::Context, 3, 0, None),
+            Header::new(Backend::Rans, ModelId::Context, 0, COUNTED, Some(1 << 40)),
+            Header::new(Backend::Cacm87, ModelId::Ppm, 5, COUNTED, Some(0)),
+            Header::new(Backend::Cacm87, ModelId::Mixing, 0, SSE | CHECKSUM, None),
+        ] {
+            let coded = write(&header);
+            assert_eq!(coded[..5], [b'S', b'A', b'C', 0x1a, VERSION]);
+            assert_eq!(read(&coded).unwrap(), header);
+        }
+    }
+    // Sets a byte of a written header and fixes up its checksum.
+    fn patch (coded: &[u8], at: usize, byte: u8) -> Vec<u8> {
+        let mut patched = coded.to_vec();
+        patched[at] = byte;
+        let end = patched.len() - 4;
+        let crc = crate::checksum::crc32(&patched[..end]);
+        patched[end..].copy_from_slice(&crc.to_be_bytes());
+        patched
+    }
+    #[test]
+    fn test_header_errors () {
+        let coded = write(&Header::new(Backend::Cacm87, ModelId::Context, 0, 0, None));
+        for (at, byte, message) in [
+            (0, b'X', "not a compressed stream"),
+            (4, VERSION + 1, "unsupported format version"),
+            (5, 9, "unknown coder backend"),
+            (6, 9, "unknown model"),
+            (8, 0x80, "unknown flags"),
+            (11, 12, "unsupported model parameters"),
+        ] {
+            let err = read(&patch(&coded, at, byte)).unwrap_err();
+            assert!(matches!(&err, Error::UnsupportedFormat(m) if m.starts_with(message)), "{err}");
+        }
+        let mut corrupt = coded.clone();
+        corrupt[7] ^= 1;
+        let err = read(&corrupt).unwrap_err();
+        assert!(matches!(err, Error::Checksum(ChecksumError { part: Checked::Header, .. })));
+        assert!(matches!(read(&coded[..7]), Err(Error::Truncated)));
+    }
+}
@@ -0,0 +1,189 @@
+//! Finite-context models that condition on the preceding symbols.
+//!
+//! A [`ContextModel`] of order `k` keeps a separate [`AdaptiveModel`] for
+//! every distinct run of `k` preceding bytes, so a symbol is coded with the
+//! statistics of what followed the same context before. Tables are created
+//! the first time their context is seen. Contexts are hashed into a fixed
+//! number of slots, which bounds memory at the cost of letting distant
+//! contexts share a table once the slots run out.
+//!
+//! ```
+//! use simple_arithmetic_coding::{ArithmeticDecoder, ArithmeticEncoder, Input, Output};
+//! use simple_arithmetic_coding::context::ContextModel;
+//!
+//! let message = b"the cat sat on the mat";
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = ArithmeticEncoder::with_model(ContextModel::new(256, 2));
+//! for &b in message.iter() {
+//!     encoder.encode(b as usize, &mut bits_out)?;
+//!     encoder.discover(b as usize);
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = ArithmeticDecoder::with_model(ContextModel::new(256, 2));
+//! decoder.begin(&mut bits_in)?;
+//! for &b in message.iter() {
+//!     let s = decoder.decode(&mut bits_in)?;
+//!     assert_eq!(s, b as usize);
+//!     decoder.discover(s);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{codec::MAX_FREQUENCY, model::{AdaptiveModel, Model}};
+
+/// The longest context, in bytes, a [`ContextModel`] can condition on.
+pub const MAX_ORDER: usize = 8;
+/// The default number of context slots is `1 << DEFAULT_HASH_BITS`.
+pub const DEFAULT_HASH_BITS: u32 = 16;
+
+/// An order-`k` model with one adaptive frequency table per context.
+///
+/// The context is formed from the low byte of each of the last `k` symbols
+/// passed to [`Model::update`]; before `k` symbols have been seen, the
+/// missing ones count as zero.
+#[derive(Clone)]
+pub struct ContextModel {
+    tables: Vec<Option<AdaptiveModel>>,
+    size: usize,
+    order: usize,
+    hash_bits: u32,
+    history: u64,
+    slot: usize
+}
+
+impl ContextModel {
+    /// Creates an order-`order` model of `size` symbols with
+    /// `1 << DEFAULT_HASH_BITS` context slots.
+    pub fn new (size: usize, order: usize) -> Self {
+        ContextModel::with_hash_bits(size, order, DEFAULT_HASH_BITS)
+    }
+
+    /// Creates an order-`order` model of `size` symbols with at most
+    /// `1 << hash_bits` context tables. Orders whose contexts all fit are
+    /// indexed directly, with no collisions.
+    pub fn with_hash_bits (size: usize, order: usize, hash_bits: u32) -> Self {
+        assert!(order <= MAX_ORDER && hash_bits <= 24);
+        assert!(size > 0 && size as u32 <= MAX_FREQUENCY);
+        let hash_bits = hash_bits.min(8 * order as u32);
+        ContextModel {
+            tables: vec![None; 1 << hash_bits],
+            size,
+            order,
+            hash_bits,
+            history: 0,
+            slot: 0
+        }
+    }
+
+    /// Returns the number of preceding bytes the model conditions on.
+    pub fn order (&self) -> usize {
+        self.order
+    }
+
+    fn table (&self) -> Option<&AdaptiveModel> {
+        self.tables[self.slot].as_ref()
+    }
+
+    fn slot (&self) -> usize {
+        if 8 * self.order as u32 <= self.hash_bits {
+            self.history as usize
+        } else {
+            (self.history.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - self.hash_bits)) as usize
+        }
+    }
+}
+
+// A context seen for the first time behaves like a fresh AdaptiveModel:
+// every symbol with frequency one.
+impl Model for ContextModel {
+    fn size (&self) -> usize {
+        self.size
+    }
+
+    fn total (&self) -> u32 {
+        self.table().map_or(self.size as u32, |t| t.total())
+    }
+
+    fn cum_freq (&self, s: usize) -> (u32, u32) {
+        self.table().map_or((s as u32, s as u32 + 1), |t| t.cum_freq(s))
+    }
+
+    fn symbol (&self, cum: u32) -> usize {
+        self.table().map_or(cum as usize, |t| t.symbol(cum))
+    }
+
+    fn update (&mut self, s: usize) {
+        let size = self.size;
+        self.tables[self.slot]
+            .get_or_insert_with(|| AdaptiveModel::new(size))
+            .update(s);
+        if self.order > 0 {
+            let mask = u64::MAX >> (64 - 8 * self.order);
+            self.history = ((self.history << 8) | (s as u64 & 0xff)) & mask;
+            self.slot = self.slot();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, Input, Output};
+
+    fn round_trip (message: &[usize], model: ContextModel) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = ArithmeticEncoder::with_model(model.clone());
+        for &s in message.iter() {
+            encoder.encode(s, &mut bits_out).unwrap();
+            encoder.discover(s);
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::with_model(model);
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder.decode(&mut bits_in).unwrap(), s);
+            decoder.discover(s);
+        }
+        coded
+    }
+
+    #[test]
+    fn test_context_round_trip_1 () {
+        let text: Vec<usize> = b"she sells sea shells by the sea shore. ".repeat(300).iter().map(|&b| b as usize).collect();
+        let order0 = round_trip(&text, ContextModel::new(257, 0)).len();
+        let order1 = round_trip(&text, ContextModel::new(257, 1)).len();
+        let order2 = round_trip(&text, ContextModel::new(257, 2)).len();
+        assert!(order1 < order0);
+        assert!(order2 < order1);
+    }
+    #[test]
+    fn test_context_hashed_1 () {
+        // Four slots for order-3 contexts force collisions.
+        let message: Vec<usize> = (0..20000).map(|i| (i * 7 + i / 13) % 256).collect();
+        let model = ContextModel::with_hash_bits(256, 3, 2);
+        assert_eq!(model.tables.len(), 4);
+        round_trip(&message, model);
+    }
+    #[test]
+    fn test_context_unseen_1 () {
+        let mut model = ContextModel::new(4, 1);
+        model.update(2);
+        assert_eq!(model.total(), 4);
+        assert_eq!(model.cum_freq(3), (3, 4));
+        assert_eq!(model.symbol(1), 1);
+        model.update(1);
+        model.update(2);
+        model.update(1);
+        assert_eq!(model.total(), 5);
+        assert_eq!(model.cum_freq(2), (2, 4));
+    }
+}
@@ -0,0 +1,114 @@
+//! The errors decoding can end with.
+//!
+//! Decoders return [`Error`], which tells a stream that ends too early or
+//! holds an impossible coder state apart from one this version cannot read
+//! and from a failure of the underlying reader or writer. The bit sources
+//! and sinks of [`io`](crate::io) report `std::io::Error`; an [`Error`]
+//! passed through them is wrapped and unwrapped again without loss, so
+//! code that returns `std::io::Error` can use `?` on either.
+
+use crate::checksum::ChecksumError;
+
+/// Why a stream could not be decoded.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The stream ended before everything it announced.
+    Truncated,
+    /// The stream led the decoder into a state no encoder produces.
+    Corrupt(String),
+    /// A checksum stored in the stream does not match.
+    Checksum(ChecksumError),
+    /// The stream is not one this version of the crate can decode.
+    UnsupportedFormat(String),
+    /// The stream decodes to more than the given maximum number of bytes.
+    TooLarge(u64),
+    /// Reading or writing failed.
+    Io(std::io::Error),
+}
+
+impl Error {
+    /// Returns a [`Error::Corrupt`] with `message`.
+    pub fn corrupt (message: impl Into<String>) -> Self {
+        Error::Corrupt(message.into())
+    }
+
+    /// Returns a [`Error::UnsupportedFormat`] with `message`.
+    pub fn unsupported (message: impl Into<String>) -> Self {
+        Error::UnsupportedFormat(message.into())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "truncated stream"),
+            Error::Corrupt(message) => write!(f, "corrupt stream: {message}"),
+            Error::Checksum(err) => err.fmt(f),
+            Error::UnsupportedFormat(message) => write!(f, "unsupported stream: {message}"),
+            Error::TooLarge(limit) => write!(f, "stream decodes to more than {limit} bytes"),
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source (&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Checksum(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChecksumError> for Error {
+    fn from (err: ChecksumError) -> Self {
+        Error::Checksum(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from (err: std::io::Error) -> Self {
+        match err.get_ref() {
+            Some(inner) if inner.is::<Error>() => *err.into_inner().unwrap().downcast::<Error>().unwrap(),
+            Some(inner) if inner.is::<ChecksumError>() => Error::Checksum(*err.into_inner().unwrap().downcast().unwrap()),
+            _ if err.kind() == std::io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from (err: Error) -> Self {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::Truncated => std::io::ErrorKind::UnexpectedEof,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Checked;
+
+    #[test]
+    fn test_io_round_trip () {
+        let err: std::io::Error = Error::corrupt("bad symbol").into();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(err), Error::Corrupt(m) if m == "bad symbol"));
+        let err: std::io::Error = Error::Truncated.into();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(matches!(Error::from(err), Error::Truncated));
+        let err = ChecksumError::check(Checked::Data, 1, 2).unwrap_err();
+        assert!(matches!(Error::from(err), Error::Checksum(ChecksumError { stored: 1, .. })));
+        let err = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "early end");
+        assert!(matches!(Error::from(err), Error::Truncated));
+        let err = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        let err: std::io::Error = Error::from(err).into();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+}
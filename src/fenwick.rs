@@ -2,6 +2,9 @@ use std::ops::{AddAssign, SubAssign};
 
 use num_traits::int::PrimInt;
 
+/// A Fenwick (binary indexed) tree of symbol frequencies, answering prefix
+/// sums and cumulative-frequency searches in `O(log n)`.
+#[derive(Clone)]
 pub struct FenwickTree<T>
 where T: PrimInt
 {
@@ -15,10 +18,11 @@ const fn lsb(i: usize) -> usize
     (i & (-i)) as usize
 }
 
-#[allow(dead_code)]
 impl<T> FenwickTree<T>
 where T: PrimInt + AddAssign + SubAssign + From<u8>
 {
+    /// Creates a tree of `size` symbols, each starting at `initial_value`
+    /// (one if `None`).
     pub fn new(size: usize, initial_value: Option<T>) -> Self {
         let mut tree: Vec<T> = vec![0.into(); size+1];
         for i in 1..tree.len() {
@@ -34,6 +38,7 @@ where T: PrimInt + AddAssign + SubAssign + From<u8>
         FenwickTree { tree: tree.into_boxed_slice() }
     }
 
+    /// Returns the sum of the frequencies of symbols `0..=i`.
     pub fn sum(&self, mut i: usize) -> T {
         assert!(i < self.tree.len()-1);
         let mut ret: T = 0.into();
@@ -45,7 +50,8 @@ where T: PrimInt + AddAssign + SubAssign + From<u8>
         ret
     }
 
-    pub fn add(&mut self, mut i: usize, amt: T) -> () {
+    /// Adds `amt` to the frequency of symbol `i`.
+    pub fn add(&mut self, mut i: usize, amt: T) {
         assert!(i < self.tree.len()-1);
         i+=1;
         while i < self.tree.len() {
@@ -54,7 +60,8 @@ where T: PrimInt + AddAssign + SubAssign + From<u8>
         }
     }
 
-    pub fn sub(&mut self, mut i: usize, amt: T) -> () {
+    /// Subtracts `amt` from the frequency of symbol `i`.
+    pub fn sub(&mut self, mut i: usize, amt: T) {
         assert!(i < self.tree.len()-1);
         i+=1;
         while i < self.tree.len() {
@@ -63,6 +70,7 @@ where T: PrimInt + AddAssign + SubAssign + From<u8>
         }
     }
 
+    /// Returns the frequency of symbol `i`.
     pub fn freq(&self, mut i: usize) -> T {
         assert!(i < self.tree.len()-1);
         i+=1;
@@ -76,7 +84,9 @@ where T: PrimInt + AddAssign + SubAssign + From<u8>
         ret
     }
 
-    pub fn scale(&mut self, factor: T) -> () {
+    /// Reduces every frequency `f` to `f - f / factor`, so a factor of two
+    /// halves it while keeping non-zero counts non-zero.
+    pub fn scale(&mut self, factor: T) {
         for i in (0..(self.tree.len()-1)).rev() {
             let a = self.freq(i)/factor;
             self.sub(i, a);
@@ -98,17 +108,31 @@ where T: PrimInt + AddAssign + SubAssign + From<u8>
         i
     }
 
+    /// Returns the number of leading symbols whose prefix sum is below `sum`.
     pub fn lower(&self, sum: T) -> usize {
         self.binary_search(sum, |a,b|{a>b})
     }
 
+    /// Returns the number of leading symbols whose prefix sum is at most
+    /// `sum`, i.e. the symbol whose cumulative range contains `sum`.
     pub fn upper (&self, sum: T) -> usize {
         self.binary_search(sum, |a,b|{a>=b})
     }
 
+    /// Returns the sum of all frequencies.
     pub fn total (&self) -> T {
         self.sum(self.tree.len()-2)
     }
+
+    /// Returns the number of symbols.
+    pub fn len (&self) -> usize {
+        self.tree.len()-1
+    }
+
+    /// Returns true if the tree holds no symbols.
+    pub fn is_empty (&self) -> bool {
+        self.len() == 0
+    }
 }
 
 #[cfg(test)]
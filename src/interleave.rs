@@ -0,0 +1,254 @@
+//! Interleaved CACM87 coding over several independent coder states.
+//!
+//! Symbol `i` of a message goes to lane `i % lanes`. Each lane is a full
+//! [`ArithmeticEncoder`]/[`ArithmeticDecoder`] with its own registers, its
+//! own copy of the model and its own bit stream, so consecutive symbols
+//! carry no data dependency on each other and the CPU can overlap their
+//! work. The coded output is the lane count, the byte length of every lane
+//! as a varint, and then the lane streams back to back.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::interleave::{InterleavedDecoder, InterleavedEncoder};
+//!
+//! let message = b"four lanes, one stream";
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = InterleavedEncoder::new(256, 4);
+//! for &b in message.iter() {
+//!     encoder.encode(b as usize)?;
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = InterleavedDecoder::new(256, 4);
+//! decoder.begin(&mut bits_in)?;
+//! for &b in message.iter() {
+//!     assert_eq!(decoder.decode()?, b as usize);
+//! }
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! assert!(InterleavedDecoder::new(256, 2).begin(&mut bits_in).is_err());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::Cursor;
+
+use crate::{
+    codec::{ArithmeticDecoder, ArithmeticEncoder},
+    error::Error,
+    io::{self, Input, Output},
+    model::{AdaptiveModel, Model}
+};
+
+/// The lane counts a stream may use.
+pub const LANES: [usize; 4] = [1, 2, 4, 8];
+
+fn check_lanes (lanes: usize) {
+    assert!(LANES.contains(&lanes), "unsupported lane count {lanes}");
+}
+
+/// Encodes symbols round-robin across several [`ArithmeticEncoder`] lanes.
+pub struct InterleavedEncoder<M = AdaptiveModel>
+where M: Model + Clone
+{
+    lanes: Vec<ArithmeticEncoder<M>>,
+    outputs: Vec<Output<Vec<u8>>>,
+    next: usize
+}
+
+impl InterleavedEncoder
+{
+    /// Creates an encoder of `lanes` lanes, each with an [`AdaptiveModel`]
+    /// of `size` symbols.
+    pub fn new (size: usize, lanes: usize) -> Self {
+        InterleavedEncoder::with_model(AdaptiveModel::new(size), lanes)
+    }
+}
+
+impl<M> InterleavedEncoder<M>
+where M: Model + Clone
+{
+    /// Creates an encoder of `lanes` lanes, each starting from a copy of
+    /// `model`. `lanes` must be one of [`LANES`].
+    pub fn with_model (model: M, lanes: usize) -> Self {
+        check_lanes(lanes);
+        InterleavedEncoder {
+            lanes: (0..lanes).map(|_| ArithmeticEncoder::with_model(model.clone())).collect(),
+            outputs: (0..lanes).map(|_| Output::new(Vec::new(), 8000)).collect(),
+            next: 0
+        }
+    }
+
+    /// Encodes `s` on the next lane and records it in that lane's model.
+    pub fn encode (&mut self, s: usize) -> Result<(), std::io::Error> {
+        let lane = &mut self.lanes[self.next];
+        lane.encode(s, &mut self.outputs[self.next])?;
+        lane.discover(s);
+        self.next = (self.next + 1) % self.lanes.len();
+        Ok(())
+    }
+
+    /// Finishes every lane and writes the interleaved stream. The sink still
+    /// has to be flushed afterwards.
+    pub fn finish (self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        let mut streams = Vec::with_capacity(self.lanes.len());
+        for (mut lane, mut output) in self.lanes.into_iter().zip(self.outputs) {
+            lane.finish(&mut output)?;
+            streams.push(output.into_inner()?);
+        }
+        bits_out.push_byte(streams.len() as u8)?;
+        for stream in streams.iter() {
+            bits_out.push_varint(stream.len() as u64)?;
+        }
+        for stream in streams.iter() {
+            for &b in stream.iter() {
+                bits_out.push_byte(b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes symbols produced by an [`InterleavedEncoder`] with the same lane
+/// count and model.
+pub struct InterleavedDecoder<M = AdaptiveModel>
+where M: Model + Clone
+{
+    lanes: Vec<ArithmeticDecoder<M>>,
+    inputs: Vec<Input<Cursor<Vec<u8>>>>,
+    next: usize
+}
+
+impl InterleavedDecoder
+{
+    /// Creates a decoder of `lanes` lanes, each with an [`AdaptiveModel`]
+    /// of `size` symbols.
+    pub fn new (size: usize, lanes: usize) -> Self {
+        InterleavedDecoder::with_model(AdaptiveModel::new(size), lanes)
+    }
+}
+
+impl<M> InterleavedDecoder<M>
+where M: Model + Clone
+{
+    /// Creates a decoder of `lanes` lanes, each starting from a copy of
+    /// `model`. `lanes` must be one of [`LANES`].
+    pub fn with_model (model: M, lanes: usize) -> Self {
+        check_lanes(lanes);
+        InterleavedDecoder {
+            lanes: (0..lanes).map(|_| ArithmeticDecoder::with_model(model.clone())).collect(),
+            inputs: Vec::new(),
+            next: 0
+        }
+    }
+
+    /// Creates a decoder with as many lanes as the stream in `bits_in`
+    /// records, and reads the lane streams.
+    pub fn from_stream (model: M, bits_in: &mut impl io::Next) -> Result<Self, std::io::Error> {
+        let lanes = read_lane_count(bits_in)?;
+        if !LANES.contains(&lanes) {
+            return Err(Error::unsupported(format!("unsupported lane count {lanes}")).into());
+        }
+        let mut decoder = InterleavedDecoder::with_model(model, lanes);
+        decoder.read_lanes(bits_in)?;
+        Ok(decoder)
+    }
+
+    /// Reads the lane streams. Fails if the stream was written with a
+    /// different number of lanes than this decoder has.
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), std::io::Error> {
+        let lanes = read_lane_count(bits_in)?;
+        if lanes != self.lanes.len() {
+            return Err(Error::unsupported(format!("stream has {lanes} lanes, decoder expects {}", self.lanes.len())).into());
+        }
+        self.read_lanes(bits_in)
+    }
+
+    fn read_lanes (&mut self, bits_in: &mut impl io::Next) -> Result<(), std::io::Error> {
+        let mut lengths = Vec::with_capacity(self.lanes.len());
+        for _ in 0..self.lanes.len() {
+            lengths.push(bits_in.next_varint()?);
+        }
+        for (lane, len) in self.lanes.iter_mut().zip(lengths) {
+            let mut stream = Vec::new();
+            for _ in 0..len {
+                let byte = bits_in.next_byte()?.ok_or(Error::Truncated)?;
+                stream.push(byte);
+            }
+            let mut input = Input::new(Cursor::new(stream))?;
+            lane.begin(&mut input)?;
+            self.inputs.push(input);
+        }
+        Ok(())
+    }
+
+    /// Decodes the next symbol from the next lane and records it in that
+    /// lane's model.
+    pub fn decode (&mut self) -> Result<usize, std::io::Error> {
+        let lane = &mut self.lanes[self.next];
+        let s = lane.decode(&mut self.inputs[self.next])?;
+        lane.discover(s);
+        self.next = (self.next + 1) % self.lanes.len();
+        Ok(s)
+    }
+}
+
+fn read_lane_count (bits_in: &mut impl io::Next) -> Result<usize, std::io::Error> {
+    Ok(bits_in.next_byte()?.ok_or(Error::Truncated)? as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode (message: &[usize], lanes: usize) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = InterleavedEncoder::new(257, lanes);
+        for &s in message.iter() {
+            encoder.encode(s).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        coded
+    }
+
+    #[test]
+    fn test_interleaved_round_trip () {
+        let message: Vec<usize> = (0..30000).map(|i| (i * 31 + i / 7) % 200).collect();
+        for lanes in LANES {
+            let coded = encode(&message, lanes);
+            assert_eq!(coded[0] as usize, lanes);
+            let mut bits_in = Input::new(coded.as_slice()).unwrap();
+            let mut decoder = InterleavedDecoder::new(257, lanes);
+            decoder.begin(&mut bits_in).unwrap();
+            for &s in message.iter() {
+                assert_eq!(decoder.decode().unwrap(), s);
+            }
+        }
+    }
+    #[test]
+    fn test_interleaved_from_stream () {
+        let message: Vec<usize> = (0..1000).map(|i| i % 13).collect();
+        let coded = encode(&message, 8);
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = InterleavedDecoder::from_stream(AdaptiveModel::new(257), &mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder.decode().unwrap(), s);
+        }
+    }
+    #[test]
+    fn test_interleaved_mismatch () {
+        let coded = encode(&[1, 2, 3], 4);
+        for lanes in [1, 2, 8] {
+            let mut bits_in = Input::new(coded.as_slice()).unwrap();
+            let err = InterleavedDecoder::new(257, lanes).begin(&mut bits_in).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+        let mut bits_in = Input::new(&[3u8, 0, 0, 0][..]).unwrap();
+        assert!(InterleavedDecoder::from_stream(AdaptiveModel::new(257), &mut bits_in).is_err());
+    }
+}
@@ -1,7 +1,14 @@
 use std::io::{BufRead, Write};
 
-use crate::bitvec::BitVec;
+use crate::{bitvec::BitVec, error::Error};
 
+/// A bit source that reads bytes from `I` and yields them most significant
+/// bit first.
+///
+/// Bytes are buffered a chunk at a time but only consumed from `I` once
+/// the input moves past them, so [`Input::into_inner`] returns `I` right
+/// after the last byte read, e.g. to go on with whatever follows a coded
+/// stream.
 pub struct Input <I>
 where I: BufRead
 {
@@ -13,22 +20,43 @@ where I: BufRead
 impl<I> Input<I>
 where I: BufRead
 {
+    /// Wraps `input_handle` and buffers its first chunk.
     pub fn new (mut input_handle: I) -> Result<Input<I>, std::io::Error> {
-        let b = input_handle.fill_buf()?;
-        let input_bits = BitVec::from_bytes(b);
-        let len = b.len();
-        input_handle.consume(len);
+        let input_bits = BitVec::from_bytes(input_handle.fill_buf()?);
         Ok(Input {
             input_handle,
             input_bits,
             idx: 0
         })
     }
+
+    /// Consumes the bytes read so far from the wrapped reader, counting a
+    /// partly read byte as read, and returns the reader.
+    pub fn into_inner (mut self) -> I {
+        self.input_handle.consume(self.idx.div_ceil(8));
+        self.input_handle
+    }
 }
 
+/// A source of bits, read most significant bit first.
 pub trait Next {
+    /// Returns the next bit, or `None` once the source is exhausted.
     fn next_bit (&mut self) -> Result<Option<bool>, std::io::Error>;
+    /// Returns the next eight bits as a byte, or `None` if fewer remain.
     fn next_byte (&mut self) -> Result<Option<u8>, std::io::Error>;
+
+    /// Reads an unsigned LEB128 integer written by [`Push::push_varint`].
+    fn next_varint (&mut self) -> Result<u64, std::io::Error> {
+        let mut value: u64 = 0;
+        for shft in (0..64).step_by(7) {
+            let byte = self.next_byte()?.ok_or(Error::Truncated)?;
+            value |= ((byte & 0x7f) as u64) << shft;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::corrupt("varint is too long").into())
+    }
 }
 
 impl<I> Next for Input<I>
@@ -36,10 +64,8 @@ where I: BufRead
 {
     fn next_bit (&mut self) -> Result<Option<bool>, std::io::Error> {
         if self.idx >= self.input_bits.len() {
-            let b = self.input_handle.fill_buf()?;
-            self.input_bits = BitVec::from_bytes(b);
-            let len = b.len();
-            self.input_handle.consume(len);
+            self.input_handle.consume(self.input_bits.len() / 8);
+            self.input_bits = BitVec::from_bytes(self.input_handle.fill_buf()?);
             self.idx = 0;
         }
         if let Some(o) = self.input_bits.get(self.idx) {
@@ -52,6 +78,10 @@ where I: BufRead
 
     fn next_byte (&mut self) -> Result<Option<u8>, std::io::Error>
     {
+        if let Some(byte) = self.input_bits.get_byte(self.idx) {
+            self.idx += 8;
+            return Ok(Some(byte));
+        }
         let mut byte: u8 = 0;
         for shft in (0..8).rev() {
             if let Some(b) = self.next_bit()? {
@@ -65,17 +95,20 @@ where I: BufRead
 }
 
 
-pub struct Output<'a, O>
+/// A bit sink that packs pushed bits into bytes and writes them to `O`.
+pub struct Output<O>
 where O: Write
 {
-    output_handle: &'a mut O,
+    output_handle: O,
     output_bits: BitVec
 }
 
-impl <'a, O> Output <'a, O>
+impl <O> Output <O>
 where O: Write
 {
-    pub fn new(output_handle: &'a mut O, buffer_capacity: usize) -> Self {
+    /// Wraps `output_handle`, buffering up to `buffer_capacity` bits (a
+    /// multiple of 8) between writes.
+    pub fn new(output_handle: O, buffer_capacity: usize) -> Self {
         assert!(buffer_capacity % 8 == 0);
         Output {
             output_handle,
@@ -83,6 +116,7 @@ where O: Write
         }
     }
 
+    /// Writes out all buffered bits, padding the last byte with zeroes.
     pub fn flush (&mut self) -> Result<(), std::io::Error> {
         if !self.output_bits.is_empty() {
             self.output_handle.write_all(&self.output_bits.get_bytes())?;
@@ -90,14 +124,48 @@ where O: Write
         }
         Ok(())
     }
+
+    /// Writes out the whole bytes buffered so far, keeping back the bits of
+    /// an unfinished last byte, and flushes the writer.
+    pub fn flush_bytes (&mut self) -> Result<(), std::io::Error> {
+        let bytes = self.output_bits.get_bytes();
+        let whole = self.output_bits.len() / 8;
+        let rest = self.output_bits.len() % 8;
+        self.output_handle.write_all(&bytes[..whole])?;
+        self.output_bits.clear();
+        for shft in (8 - rest..8).rev() {
+            self.output_bits.push((bytes[whole] >> shft) & 1 != 0);
+        }
+        self.output_handle.flush()
+    }
+
+    /// Flushes the buffered bits and returns the underlying writer.
+    pub fn into_inner (mut self) -> Result<O, std::io::Error> {
+        self.flush()?;
+        Ok(self.output_handle)
+    }
 }
 
+/// A sink for bits, written most significant bit first.
 pub trait Push {
+    /// Appends a single bit.
     fn push_bit(&mut self, bit: bool) -> Result<(), std::io::Error>;
+    /// Appends the eight bits of `byte`.
     fn push_byte(&mut self, byte: u8) -> Result<(), std::io::Error>;
+
+    /// Appends `value` as an unsigned LEB128 integer: seven bits per byte,
+    /// least significant group first, with the top bit set on all but the
+    /// last byte.
+    fn push_varint(&mut self, mut value: u64) -> Result<(), std::io::Error> {
+        while value >= 0x80 {
+            self.push_byte((value as u8 & 0x7f) | 0x80)?;
+            value >>= 7;
+        }
+        self.push_byte(value as u8)
+    }
 }
 
-impl<'a, O> Push for Output<'a, O>
+impl<O> Push for Output<O>
 where O: Write
 {
     fn push_bit(&mut self, bit: bool) -> Result<(), std::io::Error>
@@ -111,6 +179,13 @@ where O: Write
 
     fn push_byte(&mut self, byte: u8) -> Result<(), std::io::Error>
     {
+        if self.output_bits.len() % 8 == 0 {
+            if self.output_bits.len() == self.output_bits.capacity() {
+                self.flush()?;
+            }
+            self.output_bits.push_byte(byte);
+            return Ok(());
+        }
         for shft in (0..8).rev() {
             self.push_bit(((byte >> shft) & 1) != 0)?;
         }
@@ -118,6 +193,30 @@ where O: Write
     }
 }
 
+impl<N> Next for &mut N
+where N: Next + ?Sized
+{
+    fn next_bit (&mut self) -> Result<Option<bool>, std::io::Error> {
+        (**self).next_bit()
+    }
+
+    fn next_byte (&mut self) -> Result<Option<u8>, std::io::Error> {
+        (**self).next_byte()
+    }
+}
+
+impl<P> Push for &mut P
+where P: Push + ?Sized
+{
+    fn push_bit(&mut self, bit: bool) -> Result<(), std::io::Error> {
+        (**self).push_bit(bit)
+    }
+
+    fn push_byte(&mut self, byte: u8) -> Result<(), std::io::Error> {
+        (**self).push_byte(byte)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Read;
@@ -143,6 +242,23 @@ mod tests {
         assert_eq!(bytes_a, bytes_b);
     }
     #[test]
+    fn test_varint() {
+        let values = [0, 1, 127, 128, 300, 16384, u32::MAX as u64, u64::MAX];
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut output = Output::new(&mut bytes, 64);
+        for v in values {
+            output.push_varint(v).unwrap();
+        }
+        output.flush().unwrap();
+        assert_eq!(bytes[..4], [0x00, 0x01, 0x7f, 0x80]);
+
+        let mut input = Input::new(bytes.as_slice()).unwrap();
+        for v in values {
+            assert_eq!(input.next_varint().unwrap(), v);
+        }
+        assert!(input.next_varint().is_err());
+    }
+    #[test]
     fn test_output() {
         let mut stdout: Vec<u8> = Vec::new();
         let mut output = Output::new(&mut stdout, 4000);
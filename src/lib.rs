@@ -1,51 +1,826 @@
+//! Arithmetic coding, directly derived from the CACM87 C implementation.
+//!
+//! [`encode_routine`] and [`decode_routine`] compress and decompress whole
+//! byte streams; [`encode_routine_with`] picks the coder [`Backend`] and
+//! the context order. Every stream starts with a [`Header`] naming both,
+//! so [`decode_routine`] handles all of them and rejects streams it cannot
+//! decode, such as those of another format version. For untrusted input,
+//! [`decode_routine_with`] bounds how much a stream may decode to. To drive
+//! the coder one symbol at a time, use
+//! [`ArithmeticEncoder`] and [`ArithmeticDecoder`] together with a bit sink
+//! ([`Push`], e.g. [`Output`]) and a bit source ([`Next`], e.g. [`Input`]).
+//!
+//! ```
+//! use simple_arithmetic_coding::{decode_routine, encode_routine};
+//!
+//! let data = b"abracadabra".to_vec();
+//! let mut coded = Vec::new();
+//! encode_routine(&mut data.as_slice(), &mut coded)?;
+//! let mut decoded = Vec::new();
+//! decode_routine(&mut coded.as_slice(), &mut decoded)?;
+//! assert_eq!(decoded, data);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
 #![forbid(unsafe_code)]
 
 use std::io::{BufRead, Write};
 
-use io::{Next, Push};
-
-mod codec;
-mod fenwick;
-mod io;
+pub mod apm;
+pub mod binary;
+pub mod bwt;
+pub mod checksum;
+pub mod cm;
+pub mod codec;
+pub mod container;
+pub mod context;
+pub mod error;
+pub mod fenwick;
+pub mod interleave;
+pub mod io;
+pub mod lz77;
+pub mod match_model;
+pub mod model;
+pub mod ppm;
+pub mod range;
+pub mod rans;
+pub mod stream;
 mod bitvec;
 
+pub use codec::{ArithmeticDecoder, ArithmeticEncoder};
+pub use container::{Header, ModelId};
+pub use error::Error;
+pub use fenwick::FenwickTree;
+pub use io::{Input, Next, Output, Push};
+pub use model::{quantize_cdf, AdaptiveModel, Model};
+pub use stream::{ArithmeticReader, ArithmeticWriter};
+
 const SIZE: usize = 257;
 const EOF: usize = 256;
+// Counted streams code bytes alone, as their length is in the header.
+const COUNTED_SIZE: usize = 256;
+
+/// The highest context order a stream can be written with.
+pub const MAX_ORDER: usize = 4;
+
+/// The entropy coder a stream is written with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The bitwise CACM87 arithmetic coder in [`codec`].
+    #[default]
+    Cacm87,
+    /// The bytewise range coder in [`range`], faster at a small cost in ratio.
+    Range,
+    /// The rANS coder in [`rans`], with a static table built from the whole
+    /// input. Decodes fastest, but buffers the input while encoding.
+    Rans,
+}
+
+impl Backend {
+    fn id (self) -> u8 {
+        match self {
+            Backend::Cacm87 => 0,
+            Backend::Range => 1,
+            Backend::Rans => 2,
+        }
+    }
+
+    fn from_id (id: u8) -> Option<Backend> {
+        match id {
+            0 => Some(Backend::Cacm87),
+            1 => Some(Backend::Range),
+            2 => Some(Backend::Rans),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for [`encode_routine_with`].
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The entropy coder to write the stream with.
+    pub backend: Backend,
+    /// The number of interleaved coder states, one of
+    /// [`interleave::LANES`]. Only [`Backend::Cacm87`] supports more than one.
+    pub lanes: usize,
+    /// The number of preceding bytes each byte is modelled on, up to
+    /// [`MAX_ORDER`]. Order 0 uses a single frequency table; higher orders
+    /// use a [`context::ContextModel`] and need [`Backend::Cacm87`] or
+    /// [`Backend::Range`] with one lane.
+    pub order: usize,
+    /// Model bytes with PPM ([`ppm::PpmModel`]) instead, using contexts of
+    /// up to `order` bytes, which may then go up to [`ppm::MAX_ORDER`].
+    /// Needs [`Backend::Cacm87`] with one lane.
+    pub ppm: bool,
+    /// The maximum compression level: model bytes with context mixing
+    /// ([`cm::CmModel`]) and code them with the [`binary`] coder. Much
+    /// slower than the other models. The backend, lanes and order must be
+    /// left at their defaults.
+    pub cm: bool,
+    /// Refine every bit probability through adaptive probability maps
+    /// ([`apm::Apm`]) before coding it: with `cm`, those of the mixer;
+    /// otherwise those of the context model of `order`, coded bit by bit
+    /// ([`apm::SseModel`]). Needs [`Backend::Cacm87`] with one lane.
+    pub sse: bool,
+    /// Compress with LZ77 ([`lz77`]), coding literals, match lengths and
+    /// distances with adaptive models. The backend, lanes and order must be
+    /// left at their defaults.
+    pub lz77: bool,
+    /// The LZ77 window is `1 << window_bits` bytes, from
+    /// [`lz77::MIN_WINDOW_BITS`] to [`lz77::MAX_WINDOW_BITS`].
+    pub window_bits: u32,
+    /// Parse LZ77 input optimally for the coder's prices rather than
+    /// greedily. Slower to encode; decoding is unaffected.
+    pub optimal: bool,
+    /// Compress with block sorting ([`bwt`]): the Burrows-Wheeler
+    /// transform, move-to-front and zero-run coding. The backend, lanes and
+    /// order must be left at their defaults.
+    pub bwt: bool,
+    /// The largest block sorted at once, up to [`bwt::MAX_BLOCK_SIZE`].
+    pub block_size: usize,
+    /// Write the input length up front and code bytes from an alphabet of
+    /// exactly 256 symbols, instead of ending the stream with an EOF
+    /// symbol. The input is buffered while encoding, as the length goes in
+    /// the [`Header`]. rANS streams are always framed this way; context
+    /// mixing, LZ77 and block sorting frame their streams themselves and do
+    /// not take this option.
+    pub counted: bool,
+    /// End the stream with a CRC-32 of the input, which decoding verifies.
+    pub checksum: bool,
+}
+
+impl Default for Options {
+    fn default () -> Self {
+        Options {
+            backend: Backend::default(),
+            lanes: 1,
+            order: 0,
+            ppm: false,
+            cm: false,
+            sse: false,
+            lz77: false,
+            window_bits: lz77::DEFAULT_WINDOW_BITS,
+            optimal: false,
+            bwt: false,
+            block_size: bwt::DEFAULT_BLOCK_SIZE,
+            counted: false,
+            checksum: false,
+        }
+    }
+}
+
+/// Settings for [`decode_routine_with`].
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    /// Fail with [`Error::TooLarge`] rather than write more than this many
+    /// bytes. Streams are untrusted input: a few bytes can decode to
+    /// gigabytes of highly probable symbols.
+    pub max_size: Option<u64>,
+}
+
+trait SymbolEncoder {
+    fn encode_symbol (&mut self, s: usize, bits_out: &mut impl Push) -> Result<(), std::io::Error>;
+    fn finish_symbols (&mut self, bits_out: &mut impl Push) -> Result<(), std::io::Error>;
+}
+
+impl<M> SymbolEncoder for ArithmeticEncoder<M>
+where M: Model
+{
+    fn encode_symbol (&mut self, s: usize, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.encode(s, bits_out)?;
+        self.discover(s);
+        Ok(())
+    }
+
+    fn finish_symbols (&mut self, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.finish(bits_out)
+    }
+}
+
+impl<M> SymbolEncoder for range::RangeEncoder<M>
+where M: Model
+{
+    fn encode_symbol (&mut self, s: usize, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.encode(s, bits_out)?;
+        self.discover(s);
+        Ok(())
+    }
+
+    fn finish_symbols (&mut self, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.finish(bits_out)
+    }
+}
+
+trait SymbolDecoder {
+    fn begin_symbols (&mut self, bits_in: &mut impl Next) -> Result<(), std::io::Error>;
+    fn decode_symbol (&mut self, bits_in: &mut impl Next) -> Result<usize, std::io::Error>;
+}
+
+impl<M> SymbolDecoder for ArithmeticDecoder<M>
+where M: Model
+{
+    fn begin_symbols (&mut self, bits_in: &mut impl Next) -> Result<(), std::io::Error> {
+        Ok(self.begin(bits_in)?)
+    }
+
+    fn decode_symbol (&mut self, bits_in: &mut impl Next) -> Result<usize, std::io::Error> {
+        let s = self.decode(bits_in)?;
+        self.discover(s);
+        Ok(s)
+    }
+}
+
+impl<M> SymbolDecoder for range::RangeDecoder<M>
+where M: Model
+{
+    fn begin_symbols (&mut self, bits_in: &mut impl Next) -> Result<(), std::io::Error> {
+        Ok(self.begin(bits_in)?)
+    }
+
+    fn decode_symbol (&mut self, bits_in: &mut impl Next) -> Result<usize, std::io::Error> {
+        let s = self.decode(bits_in)?;
+        self.discover(s);
+        Ok(s)
+    }
+}
+
+// A PPM model together with the coder it drives.
+struct Ppm<C> {
+    model: ppm::PpmModel,
+    coder: C
+}
+
+impl Ppm<ArithmeticEncoder> {
+    fn encoder (size: usize, order: usize) -> Self {
+        Ppm { model: ppm::PpmModel::new(size, order), coder: ArithmeticEncoder::new(1) }
+    }
+}
+
+impl Ppm<ArithmeticDecoder> {
+    fn decoder (size: usize, order: usize) -> Self {
+        Ppm { model: ppm::PpmModel::new(size, order), coder: ArithmeticDecoder::new(1) }
+    }
+}
+
+impl SymbolEncoder for Ppm<ArithmeticEncoder> {
+    fn encode_symbol (&mut self, s: usize, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.model.encode(s, &mut self.coder, bits_out)
+    }
+
+    fn finish_symbols (&mut self, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.coder.finish(bits_out)
+    }
+}
+
+impl SymbolDecoder for Ppm<ArithmeticDecoder> {
+    fn begin_symbols (&mut self, bits_in: &mut impl Next) -> Result<(), std::io::Error> {
+        Ok(self.coder.begin(bits_in)?)
+    }
+
+    fn decode_symbol (&mut self, bits_in: &mut impl Next) -> Result<usize, std::io::Error> {
+        self.model.decode(&mut self.coder, bits_in)
+    }
+}
+
+// A context model refined through SSE, together with the coder it drives.
+struct Sse<C> {
+    model: apm::SseModel<context::ContextModel>,
+    coder: C
+}
+
+impl Sse<ArithmeticEncoder> {
+    fn encoder (size: usize, order: usize) -> Self {
+        Sse { model: apm::SseModel::new(context::ContextModel::new(size, order)), coder: ArithmeticEncoder::new(1) }
+    }
+}
+
+impl Sse<ArithmeticDecoder> {
+    fn decoder (size: usize, order: usize) -> Self {
+        Sse { model: apm::SseModel::new(context::ContextModel::new(size, order)), coder: ArithmeticDecoder::new(1) }
+    }
+}
+
+impl SymbolEncoder for Sse<ArithmeticEncoder> {
+    fn encode_symbol (&mut self, s: usize, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.model.encode(s, &mut self.coder, bits_out)
+    }
+
+    fn finish_symbols (&mut self, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+        self.coder.finish(bits_out)
+    }
+}
+
+impl SymbolDecoder for Sse<ArithmeticDecoder> {
+    fn begin_symbols (&mut self, bits_in: &mut impl Next) -> Result<(), std::io::Error> {
+        Ok(self.coder.begin(bits_in)?)
+    }
+
+    fn decode_symbol (&mut self, bits_in: &mut impl Next) -> Result<usize, std::io::Error> {
+        self.model.decode(&mut self.coder, bits_in)
+    }
+}
+
+fn read_all (bits_in: &mut impl Next) -> Result<Vec<u8>, std::io::Error> {
+    let mut data: Vec<u8> = Vec::new();
+    while let Some(byte) = bits_in.next_byte()? {
+        data.push(byte);
+    }
+    Ok(data)
+}
+
+// Streams that are not counted end with an EOF symbol.
+fn encode_bytes<E> (encoder: &mut E, counted: bool, bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error>
+where E: SymbolEncoder
+{
+    while let Some(byte) = bits_in.next_byte()? {
+        encoder.encode_symbol(byte as usize, bits_out)?;
+    }
+    if !counted {
+        encoder.encode_symbol(EOF, bits_out)?;
+    }
+    encoder.finish_symbols(bits_out)
+}
+
+// rANS streams are always counted, and for non-empty input hold the
+// frequency table ahead of the coded bytes.
+fn encode_rans (bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    let symbols: Vec<usize> = read_all(bits_in)?.into_iter().map(|b| b as usize).collect();
+    if symbols.is_empty() {
+        return Ok(());
+    }
+    let model = rans::StaticModel::from_symbols(COUNTED_SIZE, &symbols);
+    model.write(bits_out)?;
+    let mut encoder = rans::RansEncoder::new(model);
+    for &s in symbols.iter().rev() {
+        encoder.encode(s);
+    }
+    encoder.finish(bits_out)
+}
+
+fn encode_interleaved (lanes: usize, counted: bool, bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    let mut encoder = interleave::InterleavedEncoder::new(if counted { COUNTED_SIZE } else { SIZE }, lanes);
+    while let Some(byte) = bits_in.next_byte()? {
+        encoder.encode(byte as usize)?;
+    }
+    if !counted {
+        encoder.encode(EOF)?;
+    }
+    encoder.finish(bits_out)
+}
+
+// Context-mixed streams flag before every byte whether one follows. The
+// flag is coded at a fixed, lopsided probability, so it costs a fraction of
+// a bit per byte and twelve bits at the end.
+const MORE: u16 = binary::PROB_ONE - 1;
+
+fn encode_mixing (sse: bool, bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    let mut encoder = binary::BinaryEncoder::new();
+    let mut model = cm::CmModel::with_sse(sse);
+    while let Some(byte) = bits_in.next_byte()? {
+        encoder.encode_with_probability(MORE, false, bits_out)?;
+        model.encode(byte, &mut encoder, bits_out)?;
+    }
+    encoder.encode_with_probability(MORE, true, bits_out)?;
+    encoder.finish(bits_out)
+}
+
+// LZ77 streams parse the whole input at once, so it is buffered.
+fn encode_lz77 (window_bits: u32, optimal: bool, bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    let data = read_all(bits_in)?;
+    lz77::Lz77Encoder::with_optimal_parse(window_bits, optimal).encode(&data, bits_out)
+}
+
+// Blocks are sorted whole, so the input is buffered.
+fn encode_bwt (block_size: usize, bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    let mut encoder = bwt::BwtEncoder::new(block_size);
+    let mut block: Vec<u8> = Vec::with_capacity(block_size);
+    while let Some(byte) = bits_in.next_byte()? {
+        block.push(byte);
+        if block.len() == block_size {
+            encoder.encode_block(&block, bits_out)?;
+            block.clear();
+        }
+    }
+    if !block.is_empty() {
+        encoder.encode_block(&block, bits_out)?;
+    }
+    encoder.finish(bits_out)
+}
 
+/// Compresses everything read from `input_handle` into `output_handle` with
+/// the default [`Options`].
 pub fn encode_routine<I,O> (input_handle: &mut I, output_handle: &mut O) -> Result<(), std::io::Error>
 where I: BufRead, O: Write
 {
-    let mut encoder = codec::ArithmeticEncoder::new(SIZE);
-    let mut bits_out = io::Output::new(output_handle, 8000);
-    let mut bits_in = io::Input::new(input_handle)?;
+    encode_routine_with(input_handle, output_handle, &Options::default())
+}
 
-    while let Some(byte) = bits_in.next_byte()? {
-        encoder.encode(byte as usize, &mut bits_out)?;
-        encoder.discover(byte as usize);
+// Checks the options and returns the header of the stream they write,
+// still without the input length.
+fn header (options: &Options) -> Result<Header, std::io::Error> {
+    if !interleave::LANES.contains(&options.lanes) || (options.lanes > 1 && options.backend != Backend::Cacm87) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unsupported lane count for this backend"));
+    }
+    if options.sse && !options.cm && (options.ppm || options.lz77 || options.bwt || options.lanes > 1 || options.backend != Backend::Cacm87) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "SSE needs context mixing or a context model"));
+    }
+    if options.optimal && !options.lz77 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "optimal parsing needs LZ77"));
+    }
+    if options.counted && (options.cm || options.lz77 || options.bwt) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "this mode frames its own stream"));
+    }
+    let counted = if options.counted { container::COUNTED } else { 0 };
+    if options.bwt {
+        if options.lz77 || options.cm || options.ppm || options.order > 0 || options.lanes > 1 || options.backend != Backend::Cacm87
+            || options.block_size == 0 || options.block_size > bwt::MAX_BLOCK_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unsupported block sorting options"));
+        }
+        return Ok(Header::new(Backend::Cacm87, ModelId::Bwt, 0, 0, None));
+    }
+    if options.lz77 {
+        if options.cm || options.ppm || options.order > 0 || options.lanes > 1 || options.backend != Backend::Cacm87
+            || !(lz77::MIN_WINDOW_BITS..=lz77::MAX_WINDOW_BITS).contains(&options.window_bits) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unsupported LZ77 options"));
+        }
+        return Ok(Header::new(Backend::Cacm87, ModelId::Lz77, options.window_bits as u8, 0, None));
+    }
+    if options.cm {
+        if options.ppm || options.order > 0 || options.lanes > 1 || options.backend != Backend::Cacm87 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "context mixing takes no other model options"));
+        }
+        return Ok(Header::new(Backend::Cacm87, ModelId::Mixing, 0, if options.sse { container::SSE } else { 0 }, None));
+    }
+    if options.ppm {
+        if options.order > ppm::MAX_ORDER || options.lanes > 1 || options.backend != Backend::Cacm87 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unsupported PPM order for this backend"));
+        }
+        return Ok(Header::new(options.backend, ModelId::Ppm, options.order as u8, counted, None));
+    }
+    if options.order > MAX_ORDER || (options.order > 0 && (options.lanes > 1 || options.backend == Backend::Rans)) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unsupported context order for this backend"));
+    }
+    if options.lanes > 1 {
+        return Ok(Header::new(options.backend, ModelId::Interleaved, 0, counted, None));
+    }
+    // rANS streams always start with their length.
+    let counted = if options.backend == Backend::Rans { container::COUNTED } else { counted };
+    let sse = if options.sse { container::SSE } else { 0 };
+    Ok(Header::new(options.backend, ModelId::Context, options.order as u8, counted | sse, None))
+}
+
+fn encode_stream (options: &Options, header: &Header, bits_in: &mut impl Next, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    let counted = header.counted();
+    let size = if counted { COUNTED_SIZE } else { SIZE };
+    match (header.model, header.backend) {
+        (ModelId::Bwt, _) => encode_bwt(options.block_size, bits_in, bits_out),
+        (ModelId::Lz77, _) => encode_lz77(options.window_bits, options.optimal, bits_in, bits_out),
+        (ModelId::Mixing, _) => encode_mixing(options.sse, bits_in, bits_out),
+        (ModelId::Ppm, _) => encode_bytes(&mut Ppm::encoder(size, options.order), counted, bits_in, bits_out),
+        (ModelId::Interleaved, _) => encode_interleaved(options.lanes, counted, bits_in, bits_out),
+        // Order 0 is a single AdaptiveModel, as it always was.
+        (ModelId::Context, Backend::Cacm87) if options.sse => {
+            encode_bytes(&mut Sse::encoder(size, options.order), counted, bits_in, bits_out)
+        }
+        (ModelId::Context, Backend::Cacm87) => {
+            let model = context::ContextModel::new(size, options.order);
+            encode_bytes(&mut ArithmeticEncoder::with_model(model), counted, bits_in, bits_out)
+        }
+        (ModelId::Context, Backend::Range) => {
+            let model = context::ContextModel::new(size, options.order);
+            encode_bytes(&mut range::RangeEncoder::with_model(model), counted, bits_in, bits_out)
+        }
+        (ModelId::Context, Backend::Rans) => encode_rans(bits_in, bits_out),
     }
-    encoder.encode(EOF, &mut bits_out)?;
-    encoder.finish(&mut bits_out)?;
-    bits_out.flush()?;
+}
 
+/// Compresses everything read from `input_handle` into `output_handle`.
+pub fn encode_routine_with<I,O> (input_handle: &mut I, output_handle: &mut O, options: &Options) -> Result<(), std::io::Error>
+where I: BufRead, O: Write
+{
+    let mut writer = ArithmeticWriter::with_options(output_handle, options)?;
+    std::io::copy(input_handle, &mut writer)?;
+    writer.finish()?;
     Ok(())
 }
 
-pub fn decode_routine<I,O> (input_handle: &mut I, output_handle: &mut O) -> Result<(), std::io::Error>
+/// Decompresses a stream produced by [`encode_routine`] or
+/// [`encode_routine_with`], with the default [`DecodeOptions`].
+pub fn decode_routine<I,O> (input_handle: &mut I, output_handle: &mut O) -> Result<(), Error>
+where I: BufRead, O: Write
+{
+    decode_routine_with(input_handle, output_handle, &DecodeOptions::default())
+}
+
+/// Decompresses a stream produced by [`encode_routine`] or
+/// [`encode_routine_with`]. Streams with a missing or unsupported
+/// [`Header`] fail with [`Error::UnsupportedFormat`], those whose checksums
+/// do not match with [`Error::Checksum`], and those that end early or
+/// drive the decoder into an impossible state with [`Error::Truncated`] or
+/// [`Error::Corrupt`]. Some of the bytes decoded before an error may
+/// already have been written to `output_handle`. On success,
+/// `input_handle` is left right after the end of the stream.
+pub fn decode_routine_with<I,O> (input_handle: &mut I, output_handle: &mut O, options: &DecodeOptions) -> Result<(), Error>
 where I: BufRead, O: Write
 {
-    let mut decoder = codec::ArithmeticDecoder::new(SIZE);
-    let mut bits_out = io::Output::new(output_handle, 8000);
-    let mut bits_in = io::Input::new(input_handle)?;
+    let mut reader = ArithmeticReader::with_options(input_handle, options)?;
+    std::io::copy(&mut reader, output_handle)?;
+    reader.into_inner();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip (data: &[u8], options: &Options) -> Vec<u8> {
+        let mut coded = Vec::new();
+        encode_routine_with(&mut &data[..], &mut coded, options).unwrap();
+        let mut decoded = Vec::new();
+        decode_routine(&mut coded.as_slice(), &mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        coded
+    }
 
-    decoder.begin(&mut bits_in)?;
-    loop {
-        let s = decoder.decode(&mut bits_in)?;
-        if s == EOF {
-            break;
+    fn header_of (coded: &[u8]) -> Header {
+        Header::read(&mut Input::new(coded).unwrap()).unwrap()
+    }
+
+    fn sample () -> Vec<u8> {
+        let mut data = b"\0\0\x01 the quick brown fox jumps over the lazy dog ".repeat(200);
+        data.extend((0..=255u8).cycle().take(3000));
+        data
+    }
+
+    #[test]
+    fn test_backends_round_trip () {
+        for backend in [Backend::Cacm87, Backend::Range, Backend::Rans] {
+            let options = Options { backend, ..Options::default() };
+            round_trip(&[], &options);
+            round_trip(&[0], &options);
+            let coded = round_trip(&sample(), &options);
+            let header = header_of(&coded);
+            assert_eq!((header.backend, header.model), (backend, ModelId::Context));
+            assert_eq!(header.length, (backend == Backend::Rans).then_some(sample().len() as u64));
+            assert!(coded.len() < sample().len());
+        }
+    }
+    #[test]
+    fn test_interleaved_round_trip () {
+        for lanes in [2, 4, 8] {
+            let options = Options { lanes, ..Options::default() };
+            round_trip(&[], &options);
+            let coded = round_trip(&sample(), &options);
+            assert_eq!(header_of(&coded).model, ModelId::Interleaved);
+        }
+        let options = Options { backend: Backend::Range, lanes: 2, ..Options::default() };
+        assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+    }
+    #[test]
+    fn test_context_order_round_trip () {
+        let plain = round_trip(&sample(), &Options::default()).len();
+        for backend in [Backend::Cacm87, Backend::Range] {
+            for order in 1..=MAX_ORDER {
+                let options = Options { backend, order, ..Options::default() };
+                round_trip(&[], &options);
+                let coded = round_trip(&sample(), &options);
+                let header = header_of(&coded);
+                assert_eq!((header.backend, header.parameter), (backend, order as u8));
+                assert!(coded.len() < plain);
+            }
+        }
+        for options in [
+            Options { order: MAX_ORDER + 1, ..Options::default() },
+            Options { order: 1, lanes: 2, ..Options::default() },
+            Options { order: 1, backend: Backend::Rans, ..Options::default() },
+        ] {
+            assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+        }
+    }
+    #[test]
+    fn test_ppm_round_trip () {
+        let order2 = round_trip(&sample(), &Options { order: 2, ..Options::default() }).len();
+        for order in 0..=ppm::MAX_ORDER {
+            let options = Options { order, ppm: true, ..Options::default() };
+            round_trip(&[], &options);
+            let coded = round_trip(&sample(), &options);
+            let header = header_of(&coded);
+            assert_eq!((header.model, header.parameter), (ModelId::Ppm, order as u8));
+            if order >= 2 {
+                assert!(coded.len() < order2);
+            }
+        }
+        let options = Options { order: 2, ppm: true, backend: Backend::Range, ..Options::default() };
+        assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+    }
+    #[test]
+    fn test_mixing_round_trip () {
+        let options = Options { cm: true, ..Options::default() };
+        round_trip(&[], &options);
+        round_trip(&[0], &options);
+        let coded = round_trip(&sample(), &options);
+        assert_eq!((header_of(&coded).model, header_of(&coded).flags), (ModelId::Mixing, 0));
+        let ppm = round_trip(&sample(), &Options { order: 5, ppm: true, ..Options::default() });
+        assert!(coded.len() < ppm.len());
+        let options = Options { cm: true, sse: true, ..Options::default() };
+        round_trip(&[], &options);
+        let refined = round_trip(&sample(), &options);
+        assert_eq!(header_of(&refined).flags, container::SSE);
+        let options = Options { cm: true, order: 1, ..Options::default() };
+        assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+    }
+    #[test]
+    fn test_sse_round_trip () {
+        for order in [0, 2] {
+            let plain = round_trip(&sample(), &Options { order, ..Options::default() });
+            let options = Options { order, sse: true, ..Options::default() };
+            round_trip(&[], &options);
+            let refined = round_trip(&sample(), &options);
+            assert_eq!((header_of(&refined).model, header_of(&refined).flags), (ModelId::Context, container::SSE));
+            assert!(refined.len() < plain.len());
+        }
+        round_trip(&sample(), &Options { order: 1, sse: true, counted: true, ..Options::default() });
+        for options in [
+            Options { sse: true, ppm: true, ..Options::default() },
+            Options { sse: true, lz77: true, ..Options::default() },
+            Options { sse: true, backend: Backend::Range, ..Options::default() },
+            Options { sse: true, lanes: 2, ..Options::default() },
+        ] {
+            assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+        }
+    }
+    #[test]
+    fn test_lz77_round_trip () {
+        for window_bits in [lz77::MIN_WINDOW_BITS, 16, lz77::MAX_WINDOW_BITS] {
+            for optimal in [false, true] {
+                let options = Options { lz77: true, window_bits, optimal, ..Options::default() };
+                round_trip(&[], &options);
+                let coded = round_trip(&sample(), &options);
+                let header = header_of(&coded);
+                assert_eq!((header.model, header.parameter), (ModelId::Lz77, window_bits as u8));
+            }
+        }
+        for options in [
+            Options { lz77: true, window_bits: lz77::MAX_WINDOW_BITS + 1, ..Options::default() },
+            Options { lz77: true, cm: true, ..Options::default() },
+            Options { optimal: true, ..Options::default() },
+        ] {
+            assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+        }
+        let mut coded = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        Header::new(Backend::Cacm87, ModelId::Lz77, 40, 0, None).write(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        let err = decode_routine(&mut &coded[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedFormat(_)));
+    }
+    #[test]
+    fn test_bwt_round_trip () {
+        for block_size in [1, 1000, bwt::DEFAULT_BLOCK_SIZE] {
+            let options = Options { bwt: true, block_size, ..Options::default() };
+            round_trip(&[], &options);
+            let coded = round_trip(&sample(), &options);
+            assert_eq!(header_of(&coded).model, ModelId::Bwt);
+        }
+        for options in [
+            Options { bwt: true, block_size: 0, ..Options::default() },
+            Options { bwt: true, lz77: true, ..Options::default() },
+        ] {
+            assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+        }
+    }
+    #[test]
+    fn test_counted_round_trip () {
+        let mut settings = vec![
+            Options { backend: Backend::Range, order: 2, ..Options::default() },
+            Options { backend: Backend::Rans, ..Options::default() },
+            Options { lanes: 4, ..Options::default() },
+            Options { ppm: true, order: 5, ..Options::default() },
+        ];
+        settings.extend((0..=MAX_ORDER).map(|order| Options { order, ..Options::default() }));
+        for options in settings {
+            let counted = Options { counted: true, ..options.clone() };
+            round_trip(&[], &counted);
+            round_trip(&[255], &counted);
+            let coded = round_trip(&sample(), &counted);
+            assert_eq!(header_of(&coded).length, Some(sample().len() as u64));
+            if options.backend != Backend::Rans && options.lanes == 1 && !options.ppm {
+                // No probability is spent on the EOF symbol, which pays for
+                // the length.
+                assert!(coded.len() <= round_trip(&sample(), &options).len());
+            }
+        }
+        let options = Options { counted: true, cm: true, ..Options::default() };
+        assert!(encode_routine_with(&mut &b"abc"[..], &mut Vec::new(), &options).is_err());
+    }
+    #[test]
+    fn test_checksum_round_trip () {
+        for options in [
+            Options { checksum: true, ..Options::default() },
+            Options { checksum: true, counted: true, order: 2, ..Options::default() },
+            Options { checksum: true, backend: Backend::Rans, ..Options::default() },
+            Options { checksum: true, lanes: 2, ..Options::default() },
+            Options { checksum: true, ppm: true, order: 3, ..Options::default() },
+            Options { checksum: true, cm: true, ..Options::default() },
+            Options { checksum: true, lz77: true, ..Options::default() },
+            Options { checksum: true, bwt: true, ..Options::default() },
+        ] {
+            round_trip(&[], &options);
+            let coded = round_trip(&sample(), &options);
+            assert!(header_of(&coded).checksum());
+            let plain = round_trip(&sample(), &Options { checksum: false, ..options });
+            assert_eq!(coded.len(), plain.len() + 4);
+            assert_eq!(coded[coded.len() - 4..], checksum::crc32(&sample()).to_be_bytes());
+
+            let mut corrupt = coded.clone();
+            *corrupt.last_mut().unwrap() ^= 1;
+            let err = decode_routine(&mut corrupt.as_slice(), &mut Vec::new()).unwrap_err();
+            assert!(matches!(err, Error::Checksum(checksum::ChecksumError { part: checksum::Checked::Data, .. })));
+        }
+        let mut coded = round_trip(&sample(), &Options { checksum: true, ..Options::default() });
+        let middle = coded.len() / 2;
+        coded[middle] ^= 0x10;
+        assert!(decode_routine(&mut coded.as_slice(), &mut Vec::new()).is_err());
+    }
+    #[test]
+    fn test_unknown_backend () {
+        let mut decoded = Vec::new();
+        let err = decode_routine(&mut &[0xee, 0, 0, 0][..], &mut decoded).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedFormat(_)));
+        let err = decode_routine(&mut &container::MAGIC[..], &mut decoded).unwrap_err();
+        assert!(matches!(err, Error::Truncated));
+        // A well-formed header for a stream no encoder writes.
+        let mut coded = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        Header::new(Backend::Rans, ModelId::Ppm, 2, 0, None).write(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        let err = decode_routine(&mut &coded[..], &mut decoded).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedFormat(_)));
+    }
+    #[test]
+    fn test_damaged_streams () {
+        // Damage must end in an error or in wrong output, never in a panic.
+        for options in [
+            Options::default(),
+            Options { backend: Backend::Range, order: 2, ..Options::default() },
+            Options { backend: Backend::Rans, ..Options::default() },
+            Options { lanes: 2, counted: true, ..Options::default() },
+            Options { ppm: true, order: 3, ..Options::default() },
+            Options { lz77: true, ..Options::default() },
+            Options { bwt: true, ..Options::default() },
+        ] {
+            let coded = round_trip(&sample()[..2000], &options);
+            for at in (20..coded.len()).step_by(coded.len() / 16 + 1) {
+                let mut damaged = coded.clone();
+                damaged[at] ^= 0x5a;
+                let _ = decode_routine(&mut damaged.as_slice(), &mut Vec::new());
+                let _ = decode_routine(&mut &coded[..at], &mut Vec::new());
+            }
+        }
+    }
+    #[test]
+    fn test_trailing_data () {
+        // Decoding stops at the end of the stream, wherever the reader's chunks end.
+        for options in [
+            Options::default(),
+            Options { checksum: true, ..Options::default() },
+            Options { backend: Backend::Range, order: 1, ..Options::default() },
+            Options { backend: Backend::Rans, checksum: true, ..Options::default() },
+            Options { lanes: 2, ..Options::default() },
+            Options { ppm: true, order: 2, counted: true, ..Options::default() },
+            Options { cm: true, checksum: true, ..Options::default() },
+            Options { lz77: true, ..Options::default() },
+            Options { bwt: true, ..Options::default() },
+        ] {
+            for data in [&b""[..], &sample()[..3000]] {
+                let mut coded = Vec::new();
+                encode_routine_with(&mut &data[..], &mut coded, &options).unwrap();
+                coded.extend_from_slice(b"next");
+                let mut input_handle = std::io::BufReader::with_capacity(7, coded.as_slice());
+                let mut decoded = Vec::new();
+                decode_routine(&mut input_handle, &mut decoded).unwrap();
+                assert_eq!(decoded, data);
+                let mut rest = Vec::new();
+                std::io::Read::read_to_end(&mut input_handle, &mut rest).unwrap();
+                assert_eq!(rest, b"next", "{options:?}");
+            }
+        }
+    }
+    #[test]
+    fn test_max_size () {
+        let data = sample();
+        for options in [Options::default(), Options { counted: true, ..Options::default() }] {
+            let mut coded = Vec::new();
+            encode_routine_with(&mut data.as_slice(), &mut coded, &options).unwrap();
+            let mut decoded = Vec::new();
+            let exact = DecodeOptions { max_size: Some(data.len() as u64) };
+            decode_routine_with(&mut coded.as_slice(), &mut decoded, &exact).unwrap();
+            assert_eq!(decoded, data);
+            let short = DecodeOptions { max_size: Some(data.len() as u64 - 1) };
+            let err = decode_routine_with(&mut coded.as_slice(), &mut Vec::new(), &short).unwrap_err();
+            assert!(matches!(err, Error::TooLarge(limit) if limit == data.len() as u64 - 1));
         }
-        bits_out.push_byte(s as u8)?;
-        decoder.discover(s);
     }
-    bits_out.flush()?;
-    Ok(())
 }
@@ -0,0 +1,594 @@
+//! LZ77 with arithmetic-coded literals, lengths and distances.
+//!
+//! This is the deflate pipeline with the Huffman stage replaced by the
+//! CACM87 coder. A hash-chain match finder splits the input into
+//! [`Token`]s: literal bytes and back-references of [`MIN_MATCH`] to
+//! [`MAX_MATCH`] bytes into a window of `1 << window_bits` bytes. Every
+//! token starts with its kind, a literal, a match or the end marker, from a
+//! small adaptive model. Literals and match lengths then each have an
+//! adaptive model of their own, and distances are coded as one of
+//! `2 * window_bits` slots from a fourth, followed by the low bits of the
+//! distance, which are coded flat.
+//!
+//! The input is parsed either greedily with one step of lazy matching, or
+//! optimally: block by block, the encoder prices every literal and match
+//! at the current state of its models, `-log2(freq / total)` bits per
+//! symbol, and picks the cheapest sequence of tokens through the block.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::lz77::{Lz77Decoder, Lz77Encoder};
+//!
+//! let data = b"to be or not to be, that is the question; to be or not to be".to_vec();
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = Lz77Encoder::new(16);
+//! encoder.encode(&data, &mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut decoded, 64);
+//! Lz77Decoder::new(16).decode(&mut bits_in, &mut bits_out)?;
+//! bits_out.flush()?;
+//! drop(bits_out);
+//! assert_eq!(decoded, data);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{
+    codec::{ArithmeticDecoder, ArithmeticEncoder},
+    error::Error,
+    io,
+    model::{AdaptiveModel, Model}
+};
+
+/// The shortest back-reference.
+pub const MIN_MATCH: usize = 3;
+/// The longest back-reference.
+pub const MAX_MATCH: usize = 258;
+/// The smallest supported window, as a power of two.
+pub const MIN_WINDOW_BITS: u32 = 8;
+/// The largest supported window, as a power of two.
+pub const MAX_WINDOW_BITS: u32 = 24;
+/// The window used unless another is asked for.
+pub const DEFAULT_WINDOW_BITS: u32 = 20;
+
+// The kinds of token.
+const LITERAL: usize = 0;
+const MATCH: usize = 1;
+const END: usize = 2;
+const KINDS: usize = 3;
+pub(crate) const LITERALS: usize = 256;
+const LENGTHS: usize = MAX_MATCH - MIN_MATCH + 1;
+// Flat-coded distance bits are coded at most this many at a time.
+const EXTRA_CHUNK: u32 = 12;
+// How many earlier positions the match finder tries for each position.
+const CHAIN_DEPTH: usize = 48;
+const HASH_BITS: u32 = 16;
+// The optimal parser prices a block of this many bytes at a time, from the
+// model state at its start.
+const BLOCK: usize = 1 << 12;
+// Matches at least this long are taken without weighing shorter ones.
+const NICE_MATCH: usize = 128;
+
+/// Prices are in units of `1 / PRICE_ONE` bits.
+pub const PRICE_ONE: u32 = 256;
+
+/// One step of an LZ77 parse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// A byte coded as itself.
+    Literal(u8),
+    /// A copy of `length` bytes starting `distance` bytes back.
+    Match { length: usize, distance: usize }
+}
+
+// Splits a distance into its slot, the number of extra bits and their
+// value. Distances 1 to 4 have a slot each; beyond that, every power of two
+// is split into two slots by its second highest bit.
+fn distance_slot (distance: usize) -> (usize, u32, u32) {
+    let v = (distance - 1) as u32;
+    if v < 4 {
+        return (v as usize, 0, 0);
+    }
+    let n = 31 - v.leading_zeros();
+    let second = (v >> (n - 1)) & 1;
+    (2 * n as usize + second as usize, n - 1, v & ((1 << (n - 1)) - 1))
+}
+
+fn slot_base (slot: usize) -> (u32, u32) {
+    if slot < 4 {
+        return (slot as u32, 0);
+    }
+    let n = slot as u32 / 2;
+    ((2 | (slot as u32 & 1)) << (n - 1), n - 1)
+}
+
+/// Finds earlier occurrences of the bytes at a position through hash chains
+/// over their first [`MIN_MATCH`] bytes.
+pub struct MatchFinder {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    window_bits: u32
+}
+
+impl MatchFinder {
+    /// Creates a finder for a window of `1 << window_bits` bytes.
+    pub fn new (window_bits: u32) -> Self {
+        assert!((MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits));
+        MatchFinder {
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; 1 << window_bits],
+            window_bits
+        }
+    }
+
+    fn hash (data: &[u8], pos: usize) -> usize {
+        let v = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+        (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Records position `pos` of `data`. Positions must be inserted in
+    /// increasing order.
+    pub fn insert (&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH > data.len() {
+            return;
+        }
+        let h = MatchFinder::hash(data, pos);
+        self.prev[pos & ((1 << self.window_bits) - 1)] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+    /// Calls `found` with the length and distance of every match at `pos`
+    /// that is longer than all before it, among the inserted positions
+    /// within the window.
+    pub fn matches (&self, data: &[u8], pos: usize, mut found: impl FnMut(usize, usize)) {
+        if pos + MIN_MATCH > data.len() {
+            return;
+        }
+        let limit = (data.len() - pos).min(MAX_MATCH);
+        let mut best = MIN_MATCH - 1;
+        let mut candidate = self.head[MatchFinder::hash(data, pos)];
+        for _ in 0..CHAIN_DEPTH {
+            if candidate == 0 || candidate > pos {
+                break;
+            }
+            let start = candidate - 1;
+            let distance = pos - start;
+            if distance > 1 << self.window_bits {
+                break;
+            }
+            if data[start + best] == data[pos + best] {
+                let len = data[start..].iter().zip(data[pos..pos + limit].iter()).take_while(|(a, b)| a == b).count();
+                if len > best {
+                    best = len;
+                    found(len, distance);
+                    if len == limit {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[start & ((1 << self.window_bits) - 1)];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+    }
+
+    /// Returns the longest match at `pos`, if any.
+    pub fn longest (&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        let mut longest = None;
+        self.matches(data, pos, |len, distance| longest = Some((len, distance)));
+        longest
+    }
+}
+
+/// Parses `data` greedily, deferring a match by one byte when the next
+/// position has a longer one.
+pub fn parse (data: &[u8], window_bits: u32) -> Vec<Token> {
+    let mut finder = MatchFinder::new(window_bits);
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let current = finder.longest(data, pos);
+        finder.insert(data, pos);
+        let Some((length, distance)) = current else {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        };
+        if length < MAX_MATCH && finder.longest(data, pos + 1).is_some_and(|(next, _)| next > length) {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+        tokens.push(Token::Match { length, distance });
+        for p in pos + 1..pos + length {
+            finder.insert(data, p);
+        }
+        pos += length;
+    }
+    tokens
+}
+
+fn encode_symbol (model: &mut AdaptiveModel, s: usize, coder: &mut ArithmeticEncoder, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+    let (lower, upper) = model.cum_freq(s);
+    coder.encode_interval(lower, upper, model.total(), bits_out)?;
+    model.update(s);
+    Ok(())
+}
+
+fn decode_symbol (model: &mut AdaptiveModel, coder: &mut ArithmeticDecoder, bits_in: &mut impl io::Next) -> Result<usize, std::io::Error> {
+    let s = model.symbol(coder.decode_count(model.total())?);
+    let (lower, upper) = model.cum_freq(s);
+    coder.decode_interval(lower, upper, model.total(), bits_in)?;
+    model.update(s);
+    Ok(s)
+}
+
+// The cost of coding `s` with `model` as it stands.
+fn price (model: &AdaptiveModel, s: usize) -> u32 {
+    let (lower, upper) = model.cum_freq(s);
+    ((model.total() as f64 / (upper - lower) as f64).log2() * PRICE_ONE as f64).round() as u32
+}
+
+/// Codes [`Token`]s with adaptive models.
+pub struct Lz77Encoder {
+    kinds: AdaptiveModel,
+    literals: AdaptiveModel,
+    lengths: AdaptiveModel,
+    distances: AdaptiveModel,
+    coder: ArithmeticEncoder,
+    window_bits: u32,
+    optimal: bool
+}
+
+impl Lz77Encoder {
+    /// Creates an encoder for a window of `1 << window_bits` bytes.
+    pub fn new (window_bits: u32) -> Self {
+        Lz77Encoder::with_optimal_parse(window_bits, false)
+    }
+
+    /// Creates an encoder that, if `optimal` is set, parses its input with
+    /// [`Lz77Encoder::parse_optimal`] instead of [`parse`]. The stream
+    /// format is the same either way.
+    pub fn with_optimal_parse (window_bits: u32, optimal: bool) -> Self {
+        assert!((MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits));
+        Lz77Encoder {
+            kinds: AdaptiveModel::new(KINDS),
+            literals: AdaptiveModel::new(LITERALS),
+            lengths: AdaptiveModel::new(LENGTHS),
+            distances: AdaptiveModel::new(2 * window_bits as usize),
+            coder: ArithmeticEncoder::new(1),
+            window_bits,
+            optimal
+        }
+    }
+
+    /// Returns the window size, as a power of two.
+    pub fn window_bits (&self) -> u32 {
+        self.window_bits
+    }
+
+    /// Parses and codes all of `data`, then finishes the stream. The sink
+    /// still has to be flushed afterwards.
+    pub fn encode (&mut self, data: &[u8], bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        if self.optimal {
+            self.parse_optimal(data, |encoder, token| encoder.encode_token(token, bits_out))?;
+        } else {
+            for token in parse(data, self.window_bits) {
+                self.encode_token(token, bits_out)?;
+            }
+        }
+        self.finish(bits_out)
+    }
+
+    /// Returns what coding `token` would cost now, in units of
+    /// `1 / PRICE_ONE` bits.
+    pub fn price (&self, token: Token) -> u32 {
+        match token {
+            Token::Literal(b) => price(&self.kinds, LITERAL) + price(&self.literals, b as usize),
+            Token::Match { length, distance } => {
+                let (slot, bits, _) = distance_slot(distance);
+                price(&self.kinds, MATCH) + price(&self.lengths, length - MIN_MATCH) + price(&self.distances, slot) + bits * PRICE_ONE
+            }
+        }
+    }
+
+    /// Parses `data` into the tokens that are cheapest to code, passing
+    /// them to `emit` in order, which is expected to code them with this
+    /// encoder. Every block is priced from the model state left by the
+    /// tokens of the blocks before it, and the cheapest path through the
+    /// block is found by dynamic programming over all match lengths at the
+    /// nearest distance that reaches them.
+    pub fn parse_optimal<E> (&mut self, data: &[u8], mut emit: impl FnMut(&mut Self, Token) -> Result<(), E>) -> Result<(), E> {
+        let mut finder = MatchFinder::new(self.window_bits);
+        // For every position of the block: the cheapest cost of reaching
+        // it and the token that ends the cheapest path there.
+        let mut costs: Vec<u32> = vec![0; BLOCK + 1];
+        let mut steps: Vec<Token> = vec![Token::Literal(0); BLOCK + 1];
+        let mut path = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + BLOCK).min(data.len());
+            let (literal_price, match_price) = (price(&self.kinds, LITERAL), price(&self.kinds, MATCH));
+            let literal_prices: Vec<u32> = (0..LITERALS).map(|s| literal_price + price(&self.literals, s)).collect();
+            let length_prices: Vec<u32> = (0..LENGTHS).map(|s| match_price + price(&self.lengths, s)).collect();
+            let slot_prices: Vec<u32> = (0..self.distances.size()).map(|s| price(&self.distances, s)).collect();
+            costs[1..].fill(u32::MAX);
+            let mut skip = 0;
+            for pos in start..end {
+                let i = pos - start;
+                if skip > pos || costs[i] == u32::MAX {
+                    finder.insert(data, pos);
+                    continue;
+                }
+                let cost = costs[i] + literal_prices[data[pos] as usize];
+                if cost < costs[i + 1] {
+                    costs[i + 1] = cost;
+                    steps[i + 1] = Token::Literal(data[pos]);
+                }
+                let mut shortest = MIN_MATCH;
+                finder.matches(data, pos, |len, distance| {
+                    let len = len.min(end - pos);
+                    let (slot, bits, _) = distance_slot(distance);
+                    let distance_price = costs[i] + slot_prices[slot] + bits * PRICE_ONE;
+                    // Past NICE_MATCH, only the full length is worth trying.
+                    let from = if len >= NICE_MATCH { len } else { shortest };
+                    for length in from..=len {
+                        let cost = distance_price + length_prices[length - MIN_MATCH];
+                        if cost < costs[i + length] {
+                            costs[i + length] = cost;
+                            steps[i + length] = Token::Match { length, distance };
+                        }
+                    }
+                    shortest = shortest.max(len + 1);
+                    if len >= NICE_MATCH {
+                        skip = pos + len;
+                    }
+                });
+                finder.insert(data, pos);
+            }
+
+            let mut i = end - start;
+            while i > 0 {
+                let token = steps[i];
+                path.push(token);
+                i -= match token {
+                    Token::Literal(_) => 1,
+                    Token::Match { length, .. } => length
+                };
+            }
+            while let Some(token) = path.pop() {
+                emit(self, token)?;
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Codes one token. Matches must lie within the window.
+    pub fn encode_token (&mut self, token: Token, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        match token {
+            Token::Literal(b) => {
+                encode_symbol(&mut self.kinds, LITERAL, &mut self.coder, bits_out)?;
+                encode_symbol(&mut self.literals, b as usize, &mut self.coder, bits_out)
+            }
+            Token::Match { length, distance } => {
+                assert!((MIN_MATCH..=MAX_MATCH).contains(&length));
+                assert!(distance > 0 && distance <= 1 << self.window_bits);
+                encode_symbol(&mut self.kinds, MATCH, &mut self.coder, bits_out)?;
+                encode_symbol(&mut self.lengths, length - MIN_MATCH, &mut self.coder, bits_out)?;
+                let (slot, mut bits, extra) = distance_slot(distance);
+                encode_symbol(&mut self.distances, slot, &mut self.coder, bits_out)?;
+                while bits > 0 {
+                    let n = bits.min(EXTRA_CHUNK);
+                    bits -= n;
+                    let v = (extra >> bits) & ((1 << n) - 1);
+                    self.coder.encode_interval(v, v + 1, 1 << n, bits_out)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Codes the end marker and the final bits.
+    pub fn finish (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        encode_symbol(&mut self.kinds, END, &mut self.coder, bits_out)?;
+        self.coder.finish(bits_out)
+    }
+}
+
+/// Decodes a stream written by an [`Lz77Encoder`] with the same window.
+pub struct Lz77Decoder {
+    kinds: AdaptiveModel,
+    literals: AdaptiveModel,
+    lengths: AdaptiveModel,
+    distances: AdaptiveModel,
+    coder: ArithmeticDecoder,
+    window: Vec<u8>,
+    pos: usize
+}
+
+impl Lz77Decoder {
+    /// Creates a decoder for a window of `1 << window_bits` bytes.
+    pub fn new (window_bits: u32) -> Self {
+        assert!((MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits));
+        Lz77Decoder {
+            kinds: AdaptiveModel::new(KINDS),
+            literals: AdaptiveModel::new(LITERALS),
+            lengths: AdaptiveModel::new(LENGTHS),
+            distances: AdaptiveModel::new(2 * window_bits as usize),
+            coder: ArithmeticDecoder::new(1),
+            window: vec![0; 1 << window_bits],
+            pos: 0
+        }
+    }
+
+    /// Reads the first bits of the stream. Must be called once before
+    /// [`Lz77Decoder::decode_token`].
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), std::io::Error> {
+        Ok(self.coder.begin(bits_in)?)
+    }
+
+    /// Decodes the next token, or `None` at the end marker.
+    pub fn decode_token (&mut self, bits_in: &mut impl io::Next) -> Result<Option<Token>, std::io::Error> {
+        match decode_symbol(&mut self.kinds, &mut self.coder, bits_in)? {
+            LITERAL => {
+                let b = decode_symbol(&mut self.literals, &mut self.coder, bits_in)?;
+                return Ok(Some(Token::Literal(b as u8)));
+            }
+            END => return Ok(None),
+            _ => {}
+        }
+        let length = decode_symbol(&mut self.lengths, &mut self.coder, bits_in)? + MIN_MATCH;
+        let slot = decode_symbol(&mut self.distances, &mut self.coder, bits_in)?;
+        let (base, mut bits) = slot_base(slot);
+        let mut extra = 0;
+        while bits > 0 {
+            let n = bits.min(EXTRA_CHUNK);
+            bits -= n;
+            let v = self.coder.decode_count(1 << n)?;
+            self.coder.decode_interval(v, v + 1, 1 << n, bits_in)?;
+            extra = (extra << n) | v;
+        }
+        let distance = (base + extra) as usize + 1;
+        if distance > self.pos || distance > self.window.len() {
+            return Err(Error::corrupt("match distance out of range").into());
+        }
+        Ok(Some(Token::Match { length, distance }))
+    }
+
+    /// Decodes the next token into `bits_out`. Returns `false` at the end
+    /// marker.
+    pub fn decode_next (&mut self, bits_in: &mut impl io::Next, bits_out: &mut impl io::Push) -> Result<bool, std::io::Error> {
+        let mask = self.window.len() - 1;
+        match self.decode_token(bits_in)? {
+            Some(Token::Literal(b)) => {
+                self.window[self.pos & mask] = b;
+                self.pos += 1;
+                bits_out.push_byte(b)?;
+            }
+            Some(Token::Match { length, distance }) => {
+                for _ in 0..length {
+                    let b = self.window[(self.pos - distance) & mask];
+                    self.window[self.pos & mask] = b;
+                    self.pos += 1;
+                    bits_out.push_byte(b)?;
+                }
+            }
+            None => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    /// Decodes the whole stream into `bits_out`.
+    pub fn decode (&mut self, bits_in: &mut impl io::Next, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        self.begin(bits_in)?;
+        while self.decode_next(bits_in, bits_out)? {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    fn round_trip (data: &[u8], window_bits: u32, optimal: bool) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        Lz77Encoder::with_optimal_parse(window_bits, optimal).encode(data, &mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut decoded, 800);
+        Lz77Decoder::new(window_bits).decode(&mut bits_in, &mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+        assert_eq!(decoded, data);
+        coded
+    }
+
+    #[test]
+    fn test_distance_slots () {
+        for distance in (1..5000).chain([1 << 20, (1 << 24) - 1, 1 << 24]) {
+            let (slot, bits, extra) = distance_slot(distance);
+            let (base, base_bits) = slot_base(slot);
+            assert_eq!(bits, base_bits);
+            assert_eq!((base + extra) as usize + 1, distance);
+            assert!(slot < 2 * MAX_WINDOW_BITS as usize);
+        }
+    }
+    #[test]
+    fn test_parse () {
+        let data = b"abcabcabcabcx";
+        let tokens = parse(data, 8);
+        assert_eq!(tokens[..4], [Token::Literal(b'a'), Token::Literal(b'b'), Token::Literal(b'c'), Token::Match { length: 9, distance: 3 }]);
+        assert_eq!(tokens[4], Token::Literal(b'x'));
+    }
+    #[test]
+    fn test_price () {
+        let mut encoder = Lz77Encoder::new(16);
+        // Untrained, every kind, literal and length is equally likely.
+        let kind = ((KINDS as f64).log2() * PRICE_ONE as f64).round() as u32;
+        let literal = encoder.price(Token::Literal(b'a'));
+        assert_eq!(literal, kind + 8 * PRICE_ONE);
+        let far = encoder.price(Token::Match { length: 3, distance: 40000 });
+        assert_eq!(far, kind + 8 * PRICE_ONE + (32f64.log2() * PRICE_ONE as f64).round() as u32 + 14 * PRICE_ONE);
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        for _ in 0..100 {
+            encoder.encode_token(Token::Literal(b'a'), &mut bits_out).unwrap();
+        }
+        assert!(encoder.price(Token::Literal(b'a')) < literal / 2);
+        assert!(encoder.price(Token::Literal(b'b')) > 8 * PRICE_ONE);
+        assert!(encoder.price(Token::Match { length: 3, distance: 40000 }) > far);
+    }
+    #[test]
+    fn test_optimal_parse () {
+        // Greedy copies "abcdef" and is left with "gh" to code as literals;
+        // copying "abcd" and then "efgh" is cheaper.
+        let data = b"abcdef-efgh+abcdefgh";
+        let mut encoder = Lz77Encoder::with_optimal_parse(8, true);
+        let mut tokens = Vec::new();
+        encoder.parse_optimal(data, |_, token| { tokens.push(token); Ok::<(), ()>(()) }).unwrap();
+        let mut pos = 0;
+        for &token in tokens.iter() {
+            match token {
+                Token::Literal(b) => {
+                    assert_eq!(b, data[pos]);
+                    pos += 1;
+                }
+                Token::Match { length, distance } => {
+                    assert_eq!(data[pos..pos + length], data[pos - distance..pos - distance + length]);
+                    pos += length;
+                }
+            }
+        }
+        assert_eq!(pos, data.len());
+        // Nothing was coded, so both parses are priced alike.
+        let cost = |tokens: &[Token]| tokens.iter().map(|&t| encoder.price(t)).sum::<u32>();
+        assert!(cost(&tokens) < cost(&parse(data, 8)));
+        assert_eq!(tokens[tokens.len() - 2..], [Token::Match { length: 4, distance: 12 }, Token::Match { length: 4, distance: 9 }]);
+    }
+    #[test]
+    fn test_lz77_round_trip () {
+        round_trip(&[], 8, false);
+        round_trip(&[], 8, true);
+        let mut data = b"a simple sentence, and a simple sentence again. ".repeat(200);
+        data.extend((0..2000u32).map(|i| (i.wrapping_mul(2654435761) >> 11) as u8));
+        data.extend(b"a simple sentence, and a simple sentence again. ".repeat(10));
+        for window_bits in [8, 12, 20] {
+            let greedy = round_trip(&data, window_bits, false);
+            let optimal = round_trip(&data, window_bits, true);
+            assert!(greedy.len() < data.len() / 2);
+            // Both parses find the same long repeats here.
+            assert!(optimal.len() <= greedy.len() + 8);
+        }
+    }
+}
@@ -1,9 +1,46 @@
-use std::io::Error;
+use std::io::{BufRead, Error, Read, Write};
 
-use simple_arithmetic_coding::{decode_routine, encode_routine};
+use simple_arithmetic_coding::{decode_routine_with, encode_routine_with, Backend, DecodeOptions, Options};
 
 fn cmd_err () -> Result<(),Error> {
-    Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Usage: <bin_name> [-e | -d] --"))
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Usage: <bin_name> [-e [--counted] [--checksum] [--backend cacm|range|rans] [--lanes 1|2|4|8] [--order 0-4 [--sse] | --ppm 0-7 | --cm [--sse] | --lz77 [--window 8-24] [--optimal] | --bwt [--block BYTES]] | (-d | --test) [--max-size BYTES]] --"))
+}
+
+// Counts the bytes that pass through, for the size report of `--sse`.
+struct Counted<T> {
+    inner: T,
+    count: u64
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read (&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf (&mut self) -> Result<&[u8], Error> {
+        self.inner.fill_buf()
+    }
+
+    fn consume (&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write (&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush (&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
 }
 
 fn main() -> Result<(), Error> {
@@ -13,12 +50,92 @@ fn main() -> Result<(), Error> {
     let mut input_handle = stdin.lock();
     let mut output_handle = stdout.lock();
 
-    if args.len() != 2 {
+    let mut options = Options::default();
+    let mut decode_options = DecodeOptions::default();
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        if arg == "--cm" {
+            options.cm = true;
+            continue;
+        }
+        if arg == "--sse" {
+            options.sse = true;
+            continue;
+        }
+        if arg == "--lz77" {
+            options.lz77 = true;
+            continue;
+        }
+        if arg == "--counted" {
+            options.counted = true;
+            continue;
+        }
+        if arg == "--checksum" {
+            options.checksum = true;
+            continue;
+        }
+        if arg == "--bwt" {
+            options.bwt = true;
+            continue;
+        }
+        if arg == "--optimal" {
+            options.optimal = true;
+            continue;
+        }
+        match (arg.as_str(), rest.next().map(String::as_str)) {
+            ("--backend", Some("cacm")) => options.backend = Backend::Cacm87,
+            ("--backend", Some("range")) => options.backend = Backend::Range,
+            ("--backend", Some("rans")) => options.backend = Backend::Rans,
+            ("--lanes", Some(n)) => match n.parse() {
+                Ok(n) => options.lanes = n,
+                Err(_) => return cmd_err(),
+            },
+            ("--order", Some(n)) => match n.parse() {
+                Ok(n) => options.order = n,
+                Err(_) => return cmd_err(),
+            },
+            ("--block", Some(n)) => match n.parse() {
+                Ok(n) => options.block_size = n,
+                Err(_) => return cmd_err(),
+            },
+            ("--window", Some(n)) => match n.parse() {
+                Ok(n) => options.window_bits = n,
+                Err(_) => return cmd_err(),
+            },
+            ("--max-size", Some(n)) => match n.parse() {
+                Ok(n) => decode_options.max_size = Some(n),
+                Err(_) => return cmd_err(),
+            },
+            ("--ppm", Some(n)) => match n.parse() {
+                Ok(n) => {
+                    options.order = n;
+                    options.ppm = true;
+                }
+                Err(_) => return cmd_err(),
+            },
+            _ => return cmd_err(),
+        }
+    }
+
+    // Decoding takes no options but the size limit.
+    let decoding = args.len() == 2 || (args.len() == 4 && decode_options.max_size.is_some());
+    if args.len() < 2 {
         cmd_err()?;
     } else {
         match args[1].as_str() {
-            "-e" => encode_routine(&mut input_handle, &mut output_handle)?,
-            "-d" => decode_routine(&mut input_handle, &mut output_handle)?,
+            // SSE is a trade of speed for size, so show what it bought.
+            "-e" if decode_options.max_size.is_none() && options.sse => {
+                let mut input = Counted { inner: &mut input_handle, count: 0 };
+                let mut output = Counted { inner: &mut output_handle, count: 0 };
+                encode_routine_with(&mut input, &mut output, &options)?;
+                eprintln!("{} bytes in, {} bytes out", input.count, output.count);
+            }
+            "-e" if decode_options.max_size.is_none() => encode_routine_with(&mut input_handle, &mut output_handle, &options)?,
+            "-d" if decoding => decode_routine_with(&mut input_handle, &mut output_handle, &decode_options)?,
+            "--test" if decoding => {
+                decode_routine_with(&mut input_handle, &mut std::io::sink(), &decode_options)?;
+                writeln!(output_handle, "intact")?;
+            }
             _ => cmd_err()?,
         }
     }
@@ -0,0 +1,244 @@
+//! A match model that predicts long repeats.
+//!
+//! A [`MatchModel`] remembers the last few places where every run of
+//! [`MIN_LEN`] bytes occurred. Once the bytes just coded have occurred
+//! before, it follows whichever of those places repeats the most of them,
+//! and predicts that the byte which followed them then comes next, bit by
+//! bit, with a
+//! confidence learned separately for every match length. Long verbatim
+//! repeats then cost a small fraction of a bit per byte. It predicts bits
+//! like a [`BitModel`](crate::binary::BitModel), so it can drive the binary
+//! coder directly, and [`CmModel`](crate::cm::CmModel) mixes it with its
+//! context models.
+//!
+//! ```
+//! use simple_arithmetic_coding::match_model::MatchModel;
+//!
+//! let mut model = MatchModel::new();
+//! for &b in b"a long repeated phrase; a long repeated phrase".iter() {
+//!     for i in (0..8).rev() {
+//!         model.update((b >> i) & 1 != 0);
+//!     }
+//! }
+//! assert!(model.length() > 20);
+//! ```
+
+use crate::binary::PROB_ONE;
+
+/// The shortest repeat the model looks up.
+pub const MIN_LEN: usize = 6;
+/// The longest match length the model tracks; longer matches count as this.
+pub const MAX_LEN: usize = 65535;
+// The model looks back over the last 1 << WINDOW_BITS bytes, and indexes
+// them with a table of 1 << HASH_BITS buckets, each holding the WAYS most
+// recent positions that hashed there, latest first.
+const WINDOW_BITS: u32 = 22;
+const HASH_BITS: u32 = 18;
+const WAYS: usize = 4;
+// Match lengths are bucketed: one bucket per length up to 15, then one per
+// 16 lengths up to 255, then one for anything longer.
+const BUCKETS: usize = 32;
+const RATE: u32 = 5;
+
+fn bucket (len: usize) -> usize {
+    if len < 16 { len } else { (16 + (len - 16) / 16).min(BUCKETS - 1) }
+}
+
+/// Predicts the next bit from the longest match among recent candidates.
+pub struct MatchModel {
+    window: Box<[u8]>,
+    table: Box<[usize]>,
+    pos: usize,
+    ptr: usize,
+    len: usize,
+    c0: u32,
+    bits: u32,
+    expected: Option<bool>,
+    probs: [u16; 2 * BUCKETS]
+}
+
+impl MatchModel {
+    /// Creates a model with an empty window and no match.
+    pub fn new () -> Self {
+        let mut model = MatchModel {
+            window: vec![0; 1 << WINDOW_BITS].into_boxed_slice(),
+            table: vec![0; WAYS << HASH_BITS].into_boxed_slice(),
+            pos: 0,
+            ptr: 0,
+            len: 0,
+            c0: 1,
+            bits: 0,
+            expected: None,
+            probs: [PROB_ONE / 2; 2 * BUCKETS]
+        };
+        for (i, p) in model.probs.iter_mut().enumerate() {
+            // Start out trusting long matches more than short ones.
+            let confidence = ((PROB_ONE as usize / 2 - 64) * bucket_len(i / 2) / 64) as u16;
+            *p = if i % 2 == 1 { PROB_ONE / 2 + confidence } else { PROB_ONE / 2 - confidence };
+        }
+        model
+    }
+
+    /// Returns the length of the current match, or zero if the model has
+    /// no prediction for the current byte.
+    pub fn length (&self) -> usize {
+        if self.expected.is_some() { self.len } else { 0 }
+    }
+
+    /// Returns the bit the match predicts next, if any.
+    pub fn expected (&self) -> Option<bool> {
+        self.expected
+    }
+
+    /// Returns the probability, out of [`PROB_ONE`], that the next bit is a
+    /// one. Without a match this is one half.
+    pub fn p (&self) -> u32 {
+        match self.expected {
+            Some(bit) => self.probs[2 * bucket(self.len) + bit as usize] as u32,
+            None => PROB_ONE as u32 / 2
+        }
+    }
+
+    /// Trains the model on the coded `bit` and moves on to the next one.
+    pub fn update (&mut self, bit: bool) {
+        if let Some(expected) = self.expected {
+            let p = &mut self.probs[2 * bucket(self.len) + expected as usize];
+            let target: i32 = if bit { PROB_ONE as i32 - 1 } else { 1 };
+            *p = (*p as i32 + ((target - *p as i32) >> RATE)) as u16;
+        }
+        self.c0 = (self.c0 << 1) | bit as u32;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.next_byte(self.c0 as u8);
+            self.c0 = 1;
+            self.bits = 0;
+        }
+        self.expected = self.predict();
+    }
+
+    fn at (&self, pos: usize) -> u8 {
+        self.window[pos & ((1 << WINDOW_BITS) - 1)]
+    }
+
+    fn next_byte (&mut self, byte: u8) {
+        let mask = (1 << WINDOW_BITS) - 1;
+        self.window[self.pos & mask] = byte;
+        self.pos += 1;
+        if self.len > 0 && self.at(self.ptr) == byte && self.pos - self.ptr < 1 << WINDOW_BITS {
+            self.len = (self.len + 1).min(MAX_LEN);
+            self.ptr += 1;
+        } else {
+            self.len = 0;
+        }
+        if self.pos < MIN_LEN {
+            return;
+        }
+
+        let h = (0..MIN_LEN)
+            .fold(0u32, |h, i| (h ^ self.at(self.pos - 1 - i) as u32).wrapping_mul(0x2f0b_7a49))
+            >> (32 - HASH_BITS);
+        let bucket = h as usize * WAYS;
+        if self.len == 0 {
+            for way in 0..WAYS {
+                let candidate = self.table[bucket + way];
+                if candidate == 0 || self.pos - candidate >= 1 << WINDOW_BITS {
+                    continue;
+                }
+                let len = self.verify(candidate);
+                if len >= MIN_LEN && len > self.len {
+                    self.len = len;
+                    self.ptr = candidate;
+                }
+            }
+        }
+        self.table.copy_within(bucket..bucket + WAYS - 1, bucket + 1);
+        self.table[bucket] = self.pos;
+    }
+
+    // How many bytes before `candidate` match those just coded.
+    fn verify (&self, candidate: usize) -> usize {
+        let mut len = 0;
+        while len < candidate && len < MAX_LEN && self.at(candidate - 1 - len) == self.at(self.pos - 1 - len) {
+            len += 1;
+        }
+        len
+    }
+
+    // The next bit of the predicted byte, as long as the bits coded so far
+    // agree with it.
+    fn predict (&self) -> Option<bool> {
+        if self.len == 0 {
+            return None;
+        }
+        let predicted = self.at(self.ptr) as u32 | 0x100;
+        if predicted >> (8 - self.bits) != self.c0 {
+            return None;
+        }
+        Some((predicted >> (7 - self.bits)) & 1 != 0)
+    }
+}
+
+// A representative length for a bucket, used to seed its confidence.
+fn bucket_len (bucket: usize) -> usize {
+    if bucket < 16 { bucket } else { (16 + (bucket - 16) * 16).min(64) }
+}
+
+impl Default for MatchModel {
+    fn default () -> Self {
+        MatchModel::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed (model: &mut MatchModel, bytes: &[u8]) {
+        for &b in bytes.iter() {
+            for i in (0..8).rev() {
+                model.update((b >> i) & 1 != 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_match_predicts () {
+        let mut model = MatchModel::new();
+        feed(&mut model, b"0123456789abcdef--");
+        assert_eq!(model.length(), 0);
+        feed(&mut model, b"0123456789");
+        assert_eq!(model.length(), 10);
+        // The next byte is predicted to be 'a', 0b0110_0001.
+        assert_eq!(model.expected(), Some(false));
+        assert!(model.p() < PROB_ONE as u32 / 2);
+        feed(&mut model, b"a");
+        assert_eq!(model.length(), 11);
+    }
+    #[test]
+    fn test_match_breaks () {
+        let mut model = MatchModel::new();
+        feed(&mut model, b"abcdefgh-abcdefgh");
+        assert_eq!(model.length(), 8);
+        // '-' is predicted; '+' differs from it in its sixth bit.
+        for i in (2..8).rev() {
+            model.update((b'+' >> i) & 1 != 0);
+        }
+        assert_eq!(model.length(), 0);
+        model = MatchModel::new();
+        feed(&mut model, b"abcdefgh-abcdefgh+");
+        assert_eq!(model.length(), 0);
+        assert_eq!(model.p(), PROB_ONE as u32 / 2);
+    }
+    #[test]
+    fn test_match_longest () {
+        // The match on "Pwxyz0" follows its latest occurrence and breaks at
+        // '1'. Of the two places "wxyz01" occurred, the older one repeats
+        // more of the bytes before it and predicts 'L'; the latest one
+        // predicts 'S'.
+        let mut model = MatchModel::new();
+        feed(&mut model, b"!Pwxyz01L Qwxyz01S %Pwxyz0! #Pwxyz01");
+        assert_eq!(model.length(), 7);
+        feed(&mut model, b"L");
+        assert_eq!(model.length(), 8);
+    }
+}
@@ -0,0 +1,274 @@
+//! Probability models for the arithmetic coder.
+//!
+//! A [`Model`] maps each symbol of its alphabet to a range of cumulative
+//! frequencies. The coder only ever asks a model for those ranges, so any
+//! type implementing the trait can be plugged into
+//! [`ArithmeticEncoder::with_model`](crate::ArithmeticEncoder::with_model)
+//! and [`ArithmeticDecoder::with_model`](crate::ArithmeticDecoder::with_model).
+
+use num_traits::Float;
+
+use crate::{codec::{CodeValue, MAX_FREQUENCY}, fenwick::FenwickTree};
+
+/// A source of symbol probabilities, expressed as integer frequencies.
+pub trait Model {
+    /// Returns the number of symbols in the alphabet.
+    fn size(&self) -> usize;
+
+    /// Returns the sum of all symbol frequencies. The coder requires it to
+    /// stay at or below its `MAX_FREQUENCY` (see
+    /// [`codec::CodeValue`](crate::codec::CodeValue)).
+    fn total(&self) -> u32;
+
+    /// Returns the cumulative frequency range `[low, high)` of symbol `s`.
+    fn cum_freq(&self, s: usize) -> (u32, u32);
+
+    /// Returns the symbol whose cumulative frequency range contains `cum`.
+    fn symbol(&self, cum: u32) -> usize;
+
+    /// Records an occurrence of symbol `s`.
+    fn update(&mut self, s: usize);
+}
+
+/// An adaptive order-0 model backed by a [`FenwickTree`].
+///
+/// Every symbol starts with frequency one and gains one per occurrence. When
+/// the total reaches the rescale limit, all counts are halved.
+#[derive(Clone)]
+pub struct AdaptiveModel {
+    tree: FenwickTree<u32>,
+    limit: u32
+}
+
+impl AdaptiveModel {
+    /// Creates a model of `size` symbols that rescales at [`MAX_FREQUENCY`].
+    pub fn new (size: usize) -> Self {
+        AdaptiveModel::with_limit(size, MAX_FREQUENCY)
+    }
+
+    /// Creates a model of `size` symbols that rescales once its total
+    /// reaches `limit`.
+    pub fn with_limit (size: usize, limit: u32) -> Self {
+        assert!(size > 0 && size as u32 <= limit);
+        AdaptiveModel {
+            tree: FenwickTree::new(size, Some(1)),
+            limit
+        }
+    }
+}
+
+impl Model for AdaptiveModel {
+    fn size (&self) -> usize {
+        self.tree.len()
+    }
+
+    fn total (&self) -> u32 {
+        self.tree.total()
+    }
+
+    fn cum_freq (&self, s: usize) -> (u32, u32) {
+        let low = if s == 0 { 0 } else { self.tree.sum(s-1) };
+        (low, self.tree.sum(s))
+    }
+
+    fn symbol (&self, cum: u32) -> usize {
+        self.tree.upper(cum)
+    }
+
+    fn update (&mut self, s: usize) {
+        if self.tree.total() >= self.limit {
+            self.tree.scale(2);
+        }
+        self.tree.add(s, 1);
+    }
+}
+
+/// Quantizes a probability vector into a cumulative distribution for
+/// [`ArithmeticEncoder::encode_with_cdf`](crate::ArithmeticEncoder::encode_with_cdf)
+/// and [`ArithmeticDecoder::decode_with_cdf`](crate::ArithmeticDecoder::decode_with_cdf).
+///
+/// The result has `probs.len() + 1` entries and sums to exactly `total`,
+/// which must be at least `probs.len()` and at most the coder's
+/// `MAX_FREQUENCY` ([`MAX_FREQUENCY`] for the default width). Every
+/// symbol keeps a frequency of at least one, so any symbol remains codable
+/// however small its probability. Negative and non-finite entries count as
+/// zero; if nothing is left, the distribution is uniform. The quantization is
+/// deterministic, so encoder and decoder get the same table from the same
+/// probabilities.
+pub fn quantize_cdf<F> (probs: &[F], total: u32) -> Vec<u32>
+where F: Float
+{
+    let n = probs.len();
+    assert!(n > 0 && n as u32 <= total && total <= <u64 as CodeValue>::MAX_FREQUENCY);
+
+    let weights: Vec<f64> = probs.iter()
+        .map(|p| p.to_f64().filter(|w| w.is_finite() && *w > 0.0).unwrap_or(0.0))
+        .collect();
+    let sum: f64 = weights.iter().sum();
+    let spare = total - n as u32;
+
+    // One count per symbol up front, the rest shared out in proportion to
+    // the weights, with leftovers going to the largest remainders.
+    let mut freqs = vec![1u32; n];
+    let mut remainders: Vec<(f64, usize)> = Vec::with_capacity(n);
+    let mut assigned = 0;
+    for (i, w) in weights.iter().enumerate() {
+        let share = if sum > 0.0 { w / sum * spare as f64 } else { spare as f64 / n as f64 };
+        let whole = (share.floor() as u32).min(spare - assigned);
+        freqs[i] += whole;
+        assigned += whole;
+        remainders.push((share - whole as f64, i));
+    }
+    remainders.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    for &(_, i) in remainders.iter().cycle().take((spare - assigned) as usize) {
+        freqs[i] += 1;
+    }
+
+    let mut cdf = Vec::with_capacity(n + 1);
+    cdf.push(0);
+    for f in freqs {
+        cdf.push(cdf[cdf.len()-1] + f);
+    }
+    cdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArithmeticDecoder, ArithmeticEncoder, Error, Input, Output};
+
+    struct Skewed;
+
+    impl Model for Skewed {
+        fn size (&self) -> usize { 3 }
+        fn total (&self) -> u32 { 100 }
+        fn cum_freq (&self, s: usize) -> (u32, u32) {
+            [(0, 90), (90, 99), (99, 100)][s]
+        }
+        fn symbol (&self, cum: u32) -> usize {
+            if cum < 90 { 0 } else if cum < 99 { 1 } else { 2 }
+        }
+        fn update (&mut self, _s: usize) {}
+    }
+
+    #[test]
+    fn test_custom_model_1 () {
+        let message: Vec<usize> = (0..1000).map(|i| if i % 37 == 0 { 2 } else if i % 7 == 0 { 1 } else { 0 }).collect();
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        let mut encoder = ArithmeticEncoder::with_model(Skewed);
+        for &s in message.iter() {
+            encoder.encode(s, &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        assert!(coded.len() < 1000 / 8);
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::with_model(Skewed);
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder.decode(&mut bits_in).unwrap(), s);
+        }
+    }
+    #[test]
+    fn test_quantize_cdf_1 () {
+        let cdf = quantize_cdf(&[0.5f32, 0.25, 0.25, 0.0], 1000);
+        assert_eq!(cdf.len(), 5);
+        assert_eq!(cdf[0], 0);
+        assert_eq!(cdf[4], 1000);
+        assert!(cdf.windows(2).all(|w| w[0] < w[1]));
+        assert!(cdf[1] >= 499 && cdf[1] <= 501);
+    }
+    #[test]
+    fn test_quantize_cdf_2 () {
+        let probs = [1.0f64, 0.0, -3.0, f64::NAN, f64::INFINITY, 1e-30];
+        let cdf = quantize_cdf(&probs, 6);
+        assert_eq!(cdf, vec![0, 1, 2, 3, 4, 5, 6]);
+        let cdf = quantize_cdf(&[0.0f64; 3], 10);
+        assert_eq!(cdf[3], 10);
+        assert!(cdf.windows(2).all(|w| w[0] < w[1]));
+    }
+    #[test]
+    fn test_encode_with_cdf_1 () {
+        let steps: Vec<(Vec<f32>, usize)> = (0..500)
+            .map(|i| {
+                let hot = (i * 7) % 16;
+                let probs = (0..16).map(|s| if s == hot { 0.9 } else { 0.1 / 15.0 }).collect();
+                let s = if i % 11 == 0 { (hot + 1) % 16 } else { hot };
+                (probs, s)
+            })
+            .collect();
+
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        let mut encoder = ArithmeticEncoder::new(1);
+        for (probs, s) in steps.iter() {
+            encoder.encode_with_cdf(*s, &quantize_cdf(probs, MAX_FREQUENCY), &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        assert!(coded.len() < 500 / 8);
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::new(1);
+        decoder.begin(&mut bits_in).unwrap();
+        for (probs, s) in steps.iter() {
+            assert_eq!(decoder.decode_with_cdf(&quantize_cdf(probs, MAX_FREQUENCY), &mut bits_in).unwrap(), *s);
+        }
+    }
+    #[test]
+    fn test_invalid_cdf () {
+        use std::io::ErrorKind::InvalidInput;
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 64);
+        let mut encoder = ArithmeticEncoder::new(1);
+        for (s, cdf) in [(0, &[1, 2, 3][..]), (0, &[0, 2, 1]), (0, &[0]), (1, &[0, 2, 2]), (2, &[0, 1, 2]), (0, &[0, 1, MAX_FREQUENCY + 1])] {
+            assert_eq!(encoder.encode_with_cdf(s, cdf, &mut bits_out).unwrap_err().kind(), InvalidInput);
+        }
+        encoder.encode_with_cdf(1, &[0, 1, 3], &mut bits_out).unwrap();
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+        drop(bits_out);
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::new(1);
+        decoder.begin(&mut bits_in).unwrap();
+        for cdf in [&[1, 2, 3][..], &[0, 2, 1], &[0], &[0, 0]] {
+            assert!(matches!(decoder.decode_with_cdf(cdf, &mut bits_in), Err(Error::Io(err)) if err.kind() == InvalidInput));
+        }
+        assert_eq!(decoder.decode_with_cdf(&[0, 1, 3], &mut bits_in).unwrap(), 1);
+    }
+    #[test]
+    fn test_adaptive_cum_freq_1 () {
+        let mut model = AdaptiveModel::new(4);
+        model.update(2);
+        model.update(2);
+        assert_eq!(model.total(), 6);
+        assert_eq!(model.cum_freq(0), (0, 1));
+        assert_eq!(model.cum_freq(2), (2, 5));
+        assert_eq!(model.cum_freq(3), (5, 6));
+    }
+    #[test]
+    fn test_adaptive_symbol_1 () {
+        let mut model = AdaptiveModel::new(8);
+        for s in [0, 3, 3, 7, 7, 7] {
+            model.update(s);
+        }
+        for s in 0..model.size() {
+            let (low, high) = model.cum_freq(s);
+            for cum in low..high {
+                assert_eq!(model.symbol(cum), s);
+            }
+        }
+    }
+    #[test]
+    fn test_adaptive_limit_1 () {
+        let mut model = AdaptiveModel::with_limit(4, 16);
+        for _ in 0..100 {
+            model.update(1);
+            assert!(model.total() <= 16);
+        }
+        assert!(model.cum_freq(0).1 > 0);
+    }
+}
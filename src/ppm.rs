@@ -0,0 +1,308 @@
+//! Prediction by partial matching (PPMC) on top of the CACM87 coder.
+//!
+//! A [`PpmModel`] of order `N` keeps, for every context of up to `N`
+//! preceding bytes, the symbols that followed it and how often. A symbol is
+//! coded in the longest context that has seen it; each context on the way
+//! down that has not is left through an escape, whose frequency is the
+//! number of distinct symbols in that context (method C). Symbols already
+//! ruled out by a longer context are excluded from the shorter ones, and
+//! when no context has seen a symbol it is coded uniformly over what is
+//! left of the alphabet.
+//!
+//! Contexts are stored sparsely. Once the model holds
+//! [`DEFAULT_MAX_ENTRIES`] symbol entries (or the limit given to
+//! [`PpmModel::with_limit`]), it forgets everything and starts over, which
+//! bounds memory and lets it follow changing data.
+//!
+//! ```
+//! use simple_arithmetic_coding::{ArithmeticDecoder, ArithmeticEncoder, Input, Output};
+//! use simple_arithmetic_coding::ppm::PpmModel;
+//!
+//! let message = b"abracadabra, abracadabra";
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = ArithmeticEncoder::new(1);
+//! let mut model = PpmModel::new(256, 4);
+//! for &b in message.iter() {
+//!     model.encode(b as usize, &mut encoder, &mut bits_out)?;
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = ArithmeticDecoder::new(1);
+//! let mut model = PpmModel::new(256, 4);
+//! decoder.begin(&mut bits_in)?;
+//! for &b in message.iter() {
+//!     assert_eq!(model.decode(&mut decoder, &mut bits_in)?, b as usize);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::HashMap;
+
+use crate::{
+    codec::{ArithmeticDecoder, ArithmeticEncoder, CodeValue},
+    io,
+    model::Model
+};
+
+/// The longest context, in bytes, a [`PpmModel`] can use.
+pub const MAX_ORDER: usize = 7;
+/// The number of symbol entries after which a model starts over by default.
+pub const DEFAULT_MAX_ENTRIES: usize = 1 << 22;
+// Counts in a context are halved once their sum reaches this, which keeps
+// the total plus the escape frequency within the default coder's
+// MAX_FREQUENCY.
+pub(crate) const RESCALE: u32 = 1 << 13;
+
+/// Counts of the symbols seen in one context.
+#[derive(Clone, Default)]
+struct Context {
+    symbols: Vec<(u16, u16)>,
+    total: u32
+}
+
+impl Context {
+    fn add (&mut self, s: usize) -> bool {
+        if self.total >= RESCALE {
+            self.total = 0;
+            for (_, count) in self.symbols.iter_mut() {
+                *count -= *count / 2;
+                self.total += *count as u32;
+            }
+        }
+        self.total += 1;
+        match self.symbols.iter_mut().find(|(t, _)| *t as usize == s) {
+            Some((_, count)) => {
+                *count += 1;
+                false
+            }
+            None => {
+                self.symbols.push((s as u16, 1));
+                true
+            }
+        }
+    }
+}
+
+/// A PPMC model with symbol exclusion, coded with an [`ArithmeticEncoder`]
+/// or [`ArithmeticDecoder`].
+///
+/// The model only uses the coder's interval methods, so the coder's own
+/// model is never consulted; `ArithmeticEncoder::new(1)` will do. Totals
+/// reach up to 2^13 plus the alphabet size, so the coder must be at least
+/// the default width. The low byte of each symbol forms the context.
+#[derive(Clone)]
+pub struct PpmModel {
+    contexts: HashMap<u64, Context>,
+    size: usize,
+    order: usize,
+    history: u64,
+    seen: usize,
+    entries: usize,
+    max_entries: usize,
+    excluded: Vec<bool>
+}
+
+impl PpmModel {
+    /// Creates a model of `size` symbols that uses contexts of up to
+    /// `order` bytes.
+    pub fn new (size: usize, order: usize) -> Self {
+        PpmModel::with_limit(size, order, DEFAULT_MAX_ENTRIES)
+    }
+
+    /// Creates a model that starts over once it holds `max_entries` symbol
+    /// entries across all its contexts.
+    pub fn with_limit (size: usize, order: usize, max_entries: usize) -> Self {
+        assert!(order <= MAX_ORDER && size > 0 && size <= 1 << 12);
+        PpmModel {
+            contexts: HashMap::new(),
+            size,
+            order,
+            history: 0,
+            seen: 0,
+            entries: 0,
+            max_entries,
+            excluded: vec![false; size]
+        }
+    }
+
+    /// Returns the longest context the model uses.
+    pub fn order (&self) -> usize {
+        self.order
+    }
+
+    fn key (&self, order: usize) -> u64 {
+        let mask = if order == 0 { 0 } else { u64::MAX >> (64 - 8 * order) };
+        (self.history & mask) | (order as u64) << 56
+    }
+
+    /// Encodes `s` and records it in the model.
+    pub fn encode<M, W> (&mut self, s: usize, encoder: &mut ArithmeticEncoder<M, W>, bits_out: &mut impl io::Push) -> Result<(), std::io::Error>
+    where M: Model, W: CodeValue
+    {
+        self.excluded.fill(false);
+        let top = self.order.min(self.seen);
+        let mut found = None;
+        for order in (0..=top).rev() {
+            let Some(context) = self.contexts.get(&self.key(order)) else { continue };
+            let (mut total, mut distinct, mut hit) = (0, 0, None);
+            for &(t, count) in context.symbols.iter() {
+                if self.excluded[t as usize] {
+                    continue;
+                }
+                if t as usize == s {
+                    hit = Some((total, total + count as u32));
+                }
+                total += count as u32;
+                distinct += 1;
+            }
+            if distinct == 0 {
+                continue;
+            }
+            if let Some((lower, upper)) = hit {
+                encoder.encode_interval(lower, upper, total + distinct, bits_out)?;
+                found = Some(order);
+                break;
+            }
+            encoder.encode_interval(total, total + distinct, total + distinct, bits_out)?;
+            for &(t, _) in context.symbols.iter() {
+                self.excluded[t as usize] = true;
+            }
+        }
+        if found.is_none() {
+            let (lower, total) = self.rank(s);
+            encoder.encode_interval(lower, lower + 1, total, bits_out)?;
+        }
+        self.update(s, found);
+        Ok(())
+    }
+
+    /// Decodes the next symbol and records it in the model.
+    pub fn decode<M, W> (&mut self, decoder: &mut ArithmeticDecoder<M, W>, bits_in: &mut impl io::Next) -> Result<usize, std::io::Error>
+    where M: Model, W: CodeValue
+    {
+        self.excluded.fill(false);
+        let top = self.order.min(self.seen);
+        for order in (0..=top).rev() {
+            let Some(context) = self.contexts.get(&self.key(order)) else { continue };
+            let (mut total, mut distinct) = (0, 0);
+            for &(t, count) in context.symbols.iter() {
+                if !self.excluded[t as usize] {
+                    total += count as u32;
+                    distinct += 1;
+                }
+            }
+            if distinct == 0 {
+                continue;
+            }
+            let cum = decoder.decode_count(total + distinct)?;
+            if cum < total {
+                let mut lower = 0;
+                for &(t, count) in context.symbols.iter() {
+                    if self.excluded[t as usize] {
+                        continue;
+                    }
+                    let upper = lower + count as u32;
+                    if cum < upper {
+                        decoder.decode_interval(lower, upper, total + distinct, bits_in)?;
+                        self.update(t as usize, Some(order));
+                        return Ok(t as usize);
+                    }
+                    lower = upper;
+                }
+            }
+            decoder.decode_interval(total, total + distinct, total + distinct, bits_in)?;
+            for &(t, _) in context.symbols.iter() {
+                self.excluded[t as usize] = true;
+            }
+        }
+        let remaining = self.excluded.iter().filter(|&&e| !e).count() as u32;
+        let cum = decoder.decode_count(remaining)?;
+        let s = (0..self.size).filter(|&t| !self.excluded[t]).nth(cum as usize).unwrap();
+        decoder.decode_interval(cum, cum + 1, remaining, bits_in)?;
+        self.update(s, None);
+        Ok(s)
+    }
+
+    // The position of `s` among the symbols not excluded, and their number.
+    fn rank (&self, s: usize) -> (u32, u32) {
+        let lower = self.excluded[..s].iter().filter(|&&e| !e).count() as u32;
+        let total = self.excluded.iter().filter(|&&e| !e).count() as u32;
+        (lower, total)
+    }
+
+    // Counts `s` in the context it was found in and in every longer one
+    // (update exclusion), then moves the history on.
+    fn update (&mut self, s: usize, found: Option<usize>) {
+        if self.entries + self.order + 1 > self.max_entries {
+            self.contexts.clear();
+            self.entries = 0;
+        }
+        let top = self.order.min(self.seen);
+        let from = found.unwrap_or(0);
+        for order in from..=top {
+            let key = self.key(order);
+            if self.contexts.entry(key).or_default().add(s) {
+                self.entries += 1;
+            }
+        }
+        self.history = (self.history << 8) | (s as u64 & 0xff);
+        self.seen += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    fn round_trip (message: &[usize], model: PpmModel) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = ArithmeticEncoder::new(1);
+        let mut encoder_model = model.clone();
+        for &s in message.iter() {
+            encoder_model.encode(s, &mut encoder, &mut bits_out).unwrap();
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = ArithmeticDecoder::new(1);
+        let mut decoder_model = model;
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder_model.decode(&mut decoder, &mut bits_in).unwrap(), s);
+        }
+        coded
+    }
+
+    #[test]
+    fn test_ppm_round_trip_1 () {
+        let text: Vec<usize> = b"how much wood would a woodchuck chuck if a woodchuck could chuck wood? "
+            .repeat(100).iter().map(|&b| b as usize).collect();
+        let order1 = round_trip(&text, PpmModel::new(257, 1)).len();
+        let order4 = round_trip(&text, PpmModel::new(257, 4)).len();
+        assert!(order4 < order1);
+        assert!(order4 < text.len() / 20);
+    }
+    #[test]
+    fn test_ppm_round_trip_2 () {
+        let message: Vec<usize> = (0..20000).map(|i| (i * i + i / 3) % 257).collect();
+        round_trip(&message, PpmModel::new(257, MAX_ORDER));
+        round_trip(&message, PpmModel::new(257, 0));
+    }
+    #[test]
+    fn test_ppm_restart () {
+        let message: Vec<usize> = (0..20000).map(|i| (i * 31 + i / 7) % 200).collect();
+        let mut model = PpmModel::with_limit(256, 3, 1000);
+        round_trip(&message, model.clone());
+        for &s in message.iter() {
+            model.update(s, None);
+            assert!(model.entries <= 1000);
+        }
+    }
+}
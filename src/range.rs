@@ -0,0 +1,254 @@
+//! A byte-oriented range coder.
+//!
+//! This is Subbotin's carry-less range coder. It keeps a 32-bit `low` and
+//! `range` and renormalizes a whole byte at a time, so it does far less work
+//! per symbol than the bitwise CACM87 coder in [`codec`](crate::codec), at
+//! the cost of a little compression. It codes symbols with the same
+//! [`Model`] types and writes through the same [`Push`](io::Push) and
+//! [`Next`](io::Next) traits.
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::range::{RangeDecoder, RangeEncoder};
+//!
+//! let message = b"a range coder renormalizes bytewise";
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! let mut encoder = RangeEncoder::new(256);
+//! for &b in message.iter() {
+//!     encoder.encode(b as usize, &mut bits_out)?;
+//!     encoder.discover(b as usize);
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = RangeDecoder::new(256);
+//! decoder.begin(&mut bits_in)?;
+//! for &b in message.iter() {
+//!     let s = decoder.decode(&mut bits_in)?;
+//!     assert_eq!(s, b as usize);
+//!     decoder.discover(s);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{error::Error, io, model::{AdaptiveModel, Model}};
+
+const TOP: u32 = 1 << 24;
+const BOT: u32 = 1 << 16;
+
+/// The largest model total the range coder can work with.
+pub const MAX_FREQUENCY: u32 = BOT;
+
+/// Encodes symbols drawn from a [`Model`] a byte at a time.
+pub struct RangeEncoder<M = AdaptiveModel>
+where M: Model
+{
+    model: M,
+    low: u32,
+    range: u32
+}
+
+impl RangeEncoder
+{
+    /// Creates an encoder with an [`AdaptiveModel`] of `size` symbols.
+    pub fn new (size: usize) -> Self {
+        RangeEncoder::with_model(AdaptiveModel::new(size))
+    }
+}
+
+impl<M> RangeEncoder<M>
+where M: Model
+{
+    /// Creates an encoder that codes symbols with `model`.
+    pub fn with_model (model: M) -> Self {
+        RangeEncoder {
+            model,
+            low: 0,
+            range: u32::MAX
+        }
+    }
+
+    /// Returns the model.
+    pub fn model (&self) -> &M {
+        &self.model
+    }
+
+    /// Records an occurrence of `s` in the model.
+    pub fn discover(&mut self, s: usize) {
+        self.model.update(s);
+    }
+
+    /// Encodes `s` with the current model.
+    pub fn encode(&mut self, s: usize, bits_out: &mut impl io::Push) -> Result<(), std::io::Error>
+    {
+        let (lower, upper) = self.model.cum_freq(s);
+        let denom = self.model.total();
+        assert!(denom <= MAX_FREQUENCY);
+
+        self.range /= denom;
+        self.low = self.low.wrapping_add(lower * self.range);
+        self.range *= upper - lower;
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOT {
+                    break;
+                }
+                self.range = self.low.wrapping_neg() & (BOT - 1);
+            }
+            bits_out.push_byte((self.low >> 24) as u8)?;
+            self.low <<= 8;
+            self.range <<= 8;
+        }
+        Ok(())
+    }
+
+    /// Writes out the remaining state. The sink still has to be flushed
+    /// afterwards.
+    pub fn finish(&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        for _ in 0..4 {
+            bits_out.push_byte((self.low >> 24) as u8)?;
+            self.low <<= 8;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes symbols produced by a [`RangeEncoder`] with an identical model.
+pub struct RangeDecoder<M = AdaptiveModel>
+where M: Model
+{
+    model: M,
+    low: u32,
+    range: u32,
+    code: u32
+}
+
+impl RangeDecoder
+{
+    /// Creates a decoder with an [`AdaptiveModel`] of `size` symbols.
+    pub fn new (size: usize) -> Self {
+        RangeDecoder::with_model(AdaptiveModel::new(size))
+    }
+}
+
+impl<M> RangeDecoder<M>
+where M: Model
+{
+    /// Creates a decoder that codes symbols with `model`.
+    pub fn with_model (model: M) -> Self {
+        RangeDecoder {
+            model,
+            low: 0,
+            range: u32::MAX,
+            code: 0
+        }
+    }
+
+    /// Returns the model.
+    pub fn model (&self) -> &M {
+        &self.model
+    }
+
+    /// Reads the first bytes of the stream. Must be called once before `decode`.
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error>
+    {
+        for _ in 0..4 {
+            self.code = (self.code << 8) | next_byte(bits_in)? as u32;
+        }
+        Ok(())
+    }
+
+    /// Records an occurrence of `s` in the model, mirroring
+    /// [`RangeEncoder::discover`].
+    pub fn discover(&mut self, s: usize) {
+        self.model.update(s);
+    }
+
+    /// Decodes the next symbol with the current model. Fails with
+    /// [`Error::Corrupt`] if the model has no symbol for the coded count.
+    pub fn decode(&mut self, bits_in: &mut impl io::Next) -> Result<usize, Error>
+    {
+        let denom = self.model.total();
+        if denom == 0 || denom > MAX_FREQUENCY {
+            return Err(Error::corrupt(format!("model total {denom} is out of range")));
+        }
+
+        self.range /= denom;
+        let cum = (self.code.wrapping_sub(self.low) / self.range).min(denom - 1);
+        let s = self.model.symbol(cum);
+        if s >= self.model.size() {
+            return Err(Error::corrupt(format!("symbol {s} is outside the alphabet")));
+        }
+        let (lower, upper) = self.model.cum_freq(s);
+        if lower >= upper || upper > denom {
+            return Err(Error::corrupt(format!("interval {lower}..{upper} out of {denom} is invalid")));
+        }
+
+        self.low = self.low.wrapping_add(lower * self.range);
+        self.range *= upper - lower;
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOT {
+                    break;
+                }
+                self.range = self.low.wrapping_neg() & (BOT - 1);
+            }
+            self.code = (self.code << 8) | next_byte(bits_in)? as u32;
+            self.low <<= 8;
+            self.range <<= 8;
+        }
+        Ok(s)
+    }
+}
+
+// The decoder reads exactly the bytes the encoder wrote.
+fn next_byte (bits_in: &mut impl io::Next) -> Result<u8, Error> {
+    bits_in.next_byte()?.ok_or(Error::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    fn round_trip (message: &[usize], size: usize) -> Vec<u8> {
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        let mut encoder = RangeEncoder::new(size);
+        for &s in message.iter() {
+            encoder.encode(s, &mut bits_out).unwrap();
+            encoder.discover(s);
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = RangeDecoder::new(size);
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder.decode(&mut bits_in).unwrap(), s);
+            decoder.discover(s);
+        }
+        coded
+    }
+
+    #[test]
+    fn test_range_round_trip_1 () {
+        let message: Vec<usize> = (0..20000).map(|i| (i * i + 7 * i) % 257).collect();
+        round_trip(&message, 257);
+    }
+    #[test]
+    fn test_range_round_trip_2 () {
+        let message: Vec<usize> = (0..20000).map(|i| if i % 50 == 0 { 1 } else { 0 }).collect();
+        let coded = round_trip(&message, 2);
+        assert!(coded.len() < 20000 / 40);
+    }
+    #[test]
+    fn test_range_empty () {
+        let coded = round_trip(&[], 3);
+        assert_eq!(coded.len(), 4);
+    }
+}
@@ -0,0 +1,325 @@
+//! A range-ANS (rANS) coder with static frequency tables.
+//!
+//! rANS trades adaptivity for decode speed: the [`StaticModel`] is built
+//! from a first pass over the data and written ahead of the coded bytes, and
+//! decoding a symbol is a table lookup, a multiply and an occasional byte
+//! read. The encoder works back to front, so it buffers its output until
+//! [`RansEncoder::finish`].
+//!
+//! ```
+//! use simple_arithmetic_coding::{Input, Output};
+//! use simple_arithmetic_coding::rans::{RansDecoder, RansEncoder, StaticModel};
+//!
+//! let message: Vec<usize> = b"static tables, fast decoding".iter().map(|&b| b as usize).collect();
+//! let model = StaticModel::from_symbols(256, &message);
+//!
+//! let mut coded: Vec<u8> = Vec::new();
+//! let mut bits_out = Output::new(&mut coded, 64);
+//! model.write(&mut bits_out)?;
+//! let mut encoder = RansEncoder::new(model);
+//! for &s in message.iter().rev() {
+//!     encoder.encode(s);
+//! }
+//! encoder.finish(&mut bits_out)?;
+//! bits_out.flush()?;
+//!
+//! let mut bits_in = Input::new(coded.as_slice())?;
+//! let mut decoder = RansDecoder::new(StaticModel::read(&mut bits_in)?);
+//! decoder.begin(&mut bits_in)?;
+//! for &s in message.iter() {
+//!     assert_eq!(decoder.decode(&mut bits_in)?, s);
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{error::Error, fenwick::FenwickTree, io, model::Model};
+
+/// Frequencies of a [`StaticModel`] sum to `1 << SCALE_BITS`.
+pub const SCALE_BITS: u32 = 15;
+const SCALE: u32 = 1 << SCALE_BITS;
+const RANS_L: u32 = 1 << 23;
+
+/// A fixed symbol distribution, normalized so its total is a power of two.
+#[derive(Clone)]
+pub struct StaticModel {
+    freqs: Vec<u32>,
+    cum: Vec<u32>,
+    slots: Vec<u16>
+}
+
+impl StaticModel {
+    /// Builds a model of `size` symbols from the symbols it will code.
+    pub fn from_symbols (size: usize, symbols: &[usize]) -> Self {
+        let mut counts = vec![0u64; size];
+        for &s in symbols {
+            counts[s] += 1;
+        }
+        StaticModel::from_counts(&counts)
+    }
+
+    /// Builds a model from symbol counts. Every symbol with a non-zero count
+    /// keeps a non-zero frequency; symbols with a zero count cannot be coded.
+    /// If all counts are zero, the model is uniform.
+    pub fn from_counts (counts: &[u64]) -> Self {
+        assert!(!counts.is_empty() && counts.len() <= SCALE as usize && counts.len() <= u16::MAX as usize + 1);
+        let sum: u64 = counts.iter().sum();
+        if sum == 0 {
+            return StaticModel::from_counts(&vec![1; counts.len()]);
+        }
+
+        let mut freqs: Vec<u32> = counts.iter()
+            .map(|&c| if c == 0 { 0 } else { ((c as u128 * SCALE as u128 / sum as u128) as u32).max(1) })
+            .collect();
+        let mut total: u32 = freqs.iter().sum();
+        // Rounding up the rarest symbols can overshoot; take the excess from
+        // the most frequent ones. An undershoot goes to the most frequent.
+        while total > SCALE {
+            let (i, _) = freqs.iter().enumerate().max_by_key(|&(i, f)| (*f, std::cmp::Reverse(i))).unwrap();
+            freqs[i] -= 1;
+            total -= 1;
+        }
+        if total < SCALE {
+            let (i, _) = freqs.iter().enumerate().max_by_key(|&(i, f)| (*f, std::cmp::Reverse(i))).unwrap();
+            freqs[i] += SCALE - total;
+        }
+        StaticModel::from_freqs(freqs)
+    }
+
+    fn from_freqs (freqs: Vec<u32>) -> Self {
+        let mut tree = FenwickTree::<u32>::new(freqs.len(), Some(0));
+        for (s, &f) in freqs.iter().enumerate() {
+            tree.add(s, f);
+        }
+        let mut cum = vec![0];
+        cum.extend((0..freqs.len()).map(|s| tree.sum(s)));
+
+        let mut slots = vec![0u16; SCALE as usize];
+        for s in 0..freqs.len() {
+            slots[cum[s] as usize..cum[s+1] as usize].fill(s as u16);
+        }
+        StaticModel { freqs, cum, slots }
+    }
+
+    /// Writes the frequency table: the alphabet size, then every frequency,
+    /// all as varints.
+    pub fn write (&self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        bits_out.push_varint(self.freqs.len() as u64)?;
+        for &f in self.freqs.iter() {
+            bits_out.push_varint(f as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a frequency table written by [`StaticModel::write`].
+    pub fn read (bits_in: &mut impl io::Next) -> Result<Self, std::io::Error> {
+        let invalid = || std::io::Error::from(Error::corrupt("invalid frequency table"));
+        let size = bits_in.next_varint()?;
+        if size == 0 || size > SCALE as u64 {
+            return Err(invalid());
+        }
+        let mut freqs = Vec::with_capacity(size as usize);
+        let mut total: u64 = 0;
+        for _ in 0..size {
+            let f = bits_in.next_varint()?;
+            total += f.min(SCALE as u64 + 1);
+            freqs.push(f as u32);
+        }
+        if total != SCALE as u64 {
+            return Err(invalid());
+        }
+        Ok(StaticModel::from_freqs(freqs))
+    }
+}
+
+impl Model for StaticModel {
+    fn size (&self) -> usize {
+        self.freqs.len()
+    }
+
+    fn total (&self) -> u32 {
+        SCALE
+    }
+
+    fn cum_freq (&self, s: usize) -> (u32, u32) {
+        (self.cum[s], self.cum[s+1])
+    }
+
+    fn symbol (&self, cum: u32) -> usize {
+        self.slots[cum as usize] as usize
+    }
+
+    fn update (&mut self, _s: usize) {}
+}
+
+/// Encodes symbols with a [`StaticModel`]. Symbols must be fed in reverse
+/// order; the decoder yields them front to back.
+pub struct RansEncoder {
+    model: StaticModel,
+    state: u32,
+    bytes: Vec<u8>
+}
+
+impl RansEncoder {
+    /// Creates an encoder that codes symbols with `model`.
+    pub fn new (model: StaticModel) -> Self {
+        RansEncoder {
+            model,
+            state: RANS_L,
+            bytes: Vec::new()
+        }
+    }
+
+    /// Returns the model.
+    pub fn model (&self) -> &StaticModel {
+        &self.model
+    }
+
+    /// Encodes `s`, which must have a non-zero frequency in the model.
+    pub fn encode (&mut self, s: usize) {
+        let (start, end) = self.model.cum_freq(s);
+        let freq = end - start;
+        assert!(freq > 0);
+        let x_max = ((RANS_L >> SCALE_BITS) << 8) * freq;
+        while self.state >= x_max {
+            self.bytes.push(self.state as u8);
+            self.state >>= 8;
+        }
+        self.state = ((self.state / freq) << SCALE_BITS) + (self.state % freq) + start;
+    }
+
+    /// Writes the final state followed by all coded bytes, in the order the
+    /// decoder reads them.
+    pub fn finish (&mut self, bits_out: &mut impl io::Push) -> Result<(), std::io::Error> {
+        for b in self.state.to_be_bytes() {
+            bits_out.push_byte(b)?;
+        }
+        for &b in self.bytes.iter().rev() {
+            bits_out.push_byte(b)?;
+        }
+        self.bytes.clear();
+        self.state = RANS_L;
+        Ok(())
+    }
+}
+
+/// Decodes symbols produced by a [`RansEncoder`] with the same model.
+pub struct RansDecoder {
+    model: StaticModel,
+    state: u32
+}
+
+impl RansDecoder {
+    /// Creates a decoder that codes symbols with `model`.
+    pub fn new (model: StaticModel) -> Self {
+        RansDecoder {
+            model,
+            state: 0
+        }
+    }
+
+    /// Returns the model.
+    pub fn model (&self) -> &StaticModel {
+        &self.model
+    }
+
+    /// Reads the initial state. Must be called once before `decode`. Fails
+    /// with [`Error::Corrupt`] if no encoder can end in that state.
+    pub fn begin (&mut self, bits_in: &mut impl io::Next) -> Result<(), Error> {
+        for _ in 0..4 {
+            self.state = (self.state << 8) | next_byte(bits_in)? as u32;
+        }
+        // Every state stays at or above RANS_L, which also bounds the
+        // renormalization in `decode`.
+        if self.state < RANS_L {
+            return Err(Error::corrupt("rANS state below its lower bound"));
+        }
+        Ok(())
+    }
+
+    /// Decodes the next symbol.
+    pub fn decode (&mut self, bits_in: &mut impl io::Next) -> Result<usize, Error> {
+        let slot = self.state & (SCALE - 1);
+        let s = self.model.symbol(slot);
+        let (start, end) = self.model.cum_freq(s);
+        self.state = (end - start) * (self.state >> SCALE_BITS) + slot - start;
+        while self.state < RANS_L {
+            self.state = (self.state << 8) | next_byte(bits_in)? as u32;
+        }
+        Ok(s)
+    }
+}
+
+// The decoder reads exactly the bytes the encoder wrote.
+fn next_byte (bits_in: &mut impl io::Next) -> Result<u8, Error> {
+    bits_in.next_byte()?.ok_or(Error::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Input, Output};
+
+    fn round_trip (message: &[usize], size: usize) -> Vec<u8> {
+        let model = StaticModel::from_symbols(size, message);
+        let mut coded: Vec<u8> = Vec::new();
+        let mut bits_out = Output::new(&mut coded, 800);
+        model.write(&mut bits_out).unwrap();
+        let mut encoder = RansEncoder::new(model);
+        for &s in message.iter().rev() {
+            encoder.encode(s);
+        }
+        encoder.finish(&mut bits_out).unwrap();
+        bits_out.flush().unwrap();
+
+        let mut bits_in = Input::new(coded.as_slice()).unwrap();
+        let mut decoder = RansDecoder::new(StaticModel::read(&mut bits_in).unwrap());
+        decoder.begin(&mut bits_in).unwrap();
+        for &s in message.iter() {
+            assert_eq!(decoder.decode(&mut bits_in).unwrap(), s);
+        }
+        coded
+    }
+
+    #[test]
+    fn test_normalize_1 () {
+        let model = StaticModel::from_counts(&[1, 0, 1_000_000_000, 3, 0]);
+        assert_eq!(model.cum_freq(4).1, SCALE);
+        assert!(model.cum_freq(0).1 > model.cum_freq(0).0);
+        assert_eq!(model.cum_freq(1).0, model.cum_freq(1).1);
+        assert!(model.cum_freq(3).1 > model.cum_freq(3).0);
+    }
+    #[test]
+    fn test_normalize_2 () {
+        let counts: Vec<u64> = (0..256).map(|i| if i == 7 { 1 << 40 } else { 1 }).collect();
+        let model = StaticModel::from_counts(&counts);
+        for s in 0..256 {
+            let (low, high) = model.cum_freq(s);
+            assert!(high > low);
+            assert_eq!(model.symbol(low), s);
+            assert_eq!(model.symbol(high - 1), s);
+        }
+    }
+    #[test]
+    fn test_rans_round_trip_1 () {
+        let message: Vec<usize> = (0..50000).map(|i| (i * i + 3 * i) % 256).collect();
+        round_trip(&message, 256);
+    }
+    #[test]
+    fn test_rans_round_trip_2 () {
+        let message: Vec<usize> = (0..50000).map(|i| if i % 100 == 0 { 1 } else { 0 }).collect();
+        let coded = round_trip(&message, 2);
+        assert!(coded.len() < 50000 / 80);
+    }
+    #[test]
+    fn test_rans_bad_state () {
+        let mut decoder = RansDecoder::new(StaticModel::from_counts(&[1, 1]));
+        let mut bits_in = Input::new(&[0u8, 0, 0, 1][..]).unwrap();
+        assert!(matches!(decoder.begin(&mut bits_in), Err(Error::Corrupt(_))));
+    }
+    #[test]
+    fn test_rans_bad_table () {
+        let bytes = [2, 0x80, 0x80, 0x01, 0x01];
+        let mut bits_in = Input::new(&bytes[..]).unwrap();
+        assert!(StaticModel::read(&mut bits_in).is_err());
+    }
+}
@@ -0,0 +1,617 @@
+//! [`std::io::Write`] and [`std::io::Read`] adapters for whole streams.
+//!
+//! [`ArithmeticWriter`] compresses the bytes written to it into the stream
+//! [`encode_routine_with`](crate::encode_routine_with) would produce, and
+//! [`ArithmeticReader`] decompresses any such stream as it is read, so both
+//! fit into `std::io::copy` pipelines. Order-N context, PPM and context
+//! mixing streams are coded as the bytes arrive and block-sorted ones a
+//! block at a time; the other modes need the whole input at once and code
+//! it in [`ArithmeticWriter::finish`].
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use simple_arithmetic_coding::{ArithmeticReader, ArithmeticWriter};
+//!
+//! let mut writer = ArithmeticWriter::new(Vec::new());
+//! writer.write_all(b"abra")?;
+//! writer.write_all(b"cadabra")?;
+//! let coded = writer.finish()?;
+//!
+//! let mut decoded = String::new();
+//! ArithmeticReader::new(coded.as_slice())?.read_to_string(&mut decoded)?;
+//! assert_eq!(decoded, "abracadabra");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::{BufRead, Read, Write};
+
+use crate::{
+    binary::{BinaryDecoder, BinaryEncoder},
+    bwt::{BwtDecoder, BwtEncoder},
+    checksum::{ChecksumError, Checked, Crc32, Trailer},
+    cm::CmModel,
+    container::{self, Header, ModelId},
+    context::ContextModel,
+    error::Error,
+    interleave::InterleavedDecoder,
+    io::{Input, Next, Output, Push},
+    lz77::{self, Lz77Decoder},
+    range::{RangeDecoder, RangeEncoder},
+    rans::{RansDecoder, StaticModel},
+    ArithmeticDecoder, ArithmeticEncoder, AdaptiveModel, Backend, DecodeOptions, Model, Options, Ppm, Sse,
+    SymbolDecoder, SymbolEncoder, COUNTED_SIZE, EOF, MAX_ORDER, MORE, SIZE,
+};
+
+// The coders of the modes that code bytes as they arrive.
+enum Encoding {
+    Cacm(ArithmeticEncoder<ContextModel>),
+    Range(RangeEncoder<ContextModel>),
+    Ppm(Ppm<ArithmeticEncoder>),
+    Sse(Box<Sse<ArithmeticEncoder>>),
+    Mixing(BinaryEncoder, Box<CmModel>),
+    // The block collected so far.
+    Bwt(Box<BwtEncoder>, Vec<u8>),
+    // The other modes see the input as a whole.
+    Buffered(Vec<u8>),
+}
+
+/// A writer that compresses everything written to it.
+///
+/// The stream is only complete once [`ArithmeticWriter::finish`] has
+/// coded its end; dropping the writer leaves it cut short. Once a write
+/// has failed, the stream is cut short as well, and every later write and
+/// `finish` fail too.
+pub struct ArithmeticWriter<W>
+where W: Write
+{
+    bits_out: Output<W>,
+    options: Options,
+    header: Header,
+    encoding: Encoding,
+    crc: Crc32,
+    started: bool,
+    // Set once coding has failed partway through a write.
+    failed: bool
+}
+
+impl<W> ArithmeticWriter<W>
+where W: Write
+{
+    /// Compresses into `inner` with the default [`Options`].
+    pub fn new (inner: W) -> Self {
+        // The default options are always valid.
+        Self::with_options(inner, &Options::default()).unwrap()
+    }
+
+    /// Compresses into `inner` with `options`, which fail here if they do
+    /// not go together.
+    pub fn with_options (inner: W, options: &Options) -> Result<Self, std::io::Error> {
+        let mut header = crate::header(options)?;
+        if options.checksum {
+            header.flags |= container::CHECKSUM;
+        }
+        let order = options.order;
+        let encoding = match (header.model, header.backend) {
+            _ if header.counted() => Encoding::Buffered(Vec::new()),
+            (ModelId::Context, Backend::Cacm87) if options.sse => Encoding::Sse(Box::new(Sse::encoder(SIZE, order))),
+            (ModelId::Context, Backend::Cacm87) => Encoding::Cacm(ArithmeticEncoder::with_model(ContextModel::new(SIZE, order))),
+            (ModelId::Context, Backend::Range) => Encoding::Range(RangeEncoder::with_model(ContextModel::new(SIZE, order))),
+            (ModelId::Ppm, _) => Encoding::Ppm(Ppm::encoder(SIZE, order)),
+            (ModelId::Mixing, _) => Encoding::Mixing(BinaryEncoder::new(), Box::new(CmModel::with_sse(options.sse))),
+            (ModelId::Bwt, _) => Encoding::Bwt(Box::new(BwtEncoder::new(options.block_size)), Vec::new()),
+            _ => Encoding::Buffered(Vec::new()),
+        };
+        Ok(ArithmeticWriter {
+            bits_out: Output::new(inner, 8000),
+            options: options.clone(),
+            header,
+            encoding,
+            crc: Crc32::new(),
+            started: false,
+            failed: false
+        })
+    }
+
+    // Streaming modes write the header before their first byte.
+    fn start (&mut self) -> Result<(), std::io::Error> {
+        if self.failed {
+            return Err(std::io::Error::other("an earlier write failed"));
+        }
+        if !self.started && !matches!(self.encoding, Encoding::Buffered(_)) {
+            self.header.write(&mut self.bits_out)?;
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    /// Codes the end of the stream and its checksum, if any, and returns
+    /// the inner writer.
+    pub fn finish (mut self) -> Result<W, std::io::Error> {
+        self.start()?;
+        let bits_out = &mut self.bits_out;
+        match &mut self.encoding {
+            Encoding::Cacm(encoder) => finish_symbols(encoder, bits_out)?,
+            Encoding::Range(encoder) => finish_symbols(encoder, bits_out)?,
+            Encoding::Ppm(encoder) => finish_symbols(encoder, bits_out)?,
+            Encoding::Sse(encoder) => finish_symbols(encoder.as_mut(), bits_out)?,
+            Encoding::Mixing(encoder, _) => {
+                encoder.encode_with_probability(MORE, true, bits_out)?;
+                encoder.finish(bits_out)?;
+            }
+            Encoding::Bwt(encoder, block) => {
+                if !block.is_empty() {
+                    encoder.encode_block(block, bits_out)?;
+                }
+                encoder.finish(bits_out)?;
+            }
+            Encoding::Buffered(data) => {
+                if self.header.counted() {
+                    // The length goes in the header.
+                    self.header.length = Some(data.len() as u64);
+                }
+                self.header.write(bits_out)?;
+                crate::encode_stream(&self.options, &self.header, &mut Input::new(data.as_slice())?, bits_out)?;
+            }
+        }
+        // The trailer starts on a byte boundary.
+        self.bits_out.flush()?;
+        if self.header.checksum() {
+            for byte in self.crc.value().to_be_bytes() {
+                self.bits_out.push_byte(byte)?;
+            }
+        }
+        self.bits_out.into_inner()
+    }
+}
+
+fn finish_symbols (encoder: &mut impl SymbolEncoder, bits_out: &mut impl Push) -> Result<(), std::io::Error> {
+    encoder.encode_symbol(EOF, bits_out)?;
+    encoder.finish_symbols(bits_out)
+}
+
+impl<W> Write for ArithmeticWriter<W>
+where W: Write
+{
+    fn write (&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.start()?;
+        // Part of `buf` may be coded already when this fails, which the
+        // checksum cannot reflect.
+        self.failed = true;
+        let bits_out = &mut self.bits_out;
+        match &mut self.encoding {
+            Encoding::Cacm(encoder) => buf.iter().try_for_each(|&b| encoder.encode_symbol(b as usize, bits_out))?,
+            Encoding::Range(encoder) => buf.iter().try_for_each(|&b| encoder.encode_symbol(b as usize, bits_out))?,
+            Encoding::Ppm(encoder) => buf.iter().try_for_each(|&b| encoder.encode_symbol(b as usize, bits_out))?,
+            Encoding::Sse(encoder) => buf.iter().try_for_each(|&b| encoder.encode_symbol(b as usize, bits_out))?,
+            Encoding::Mixing(encoder, model) => {
+                for &b in buf {
+                    encoder.encode_with_probability(MORE, false, bits_out)?;
+                    model.encode(b, encoder, bits_out)?;
+                }
+            }
+            Encoding::Bwt(encoder, block) => {
+                for chunk in buf.chunks(encoder.block_size()) {
+                    let take = chunk.len().min(encoder.block_size() - block.len());
+                    block.extend_from_slice(&chunk[..take]);
+                    if block.len() == encoder.block_size() {
+                        encoder.encode_block(block, bits_out)?;
+                        block.clear();
+                    }
+                    block.extend_from_slice(&chunk[take..]);
+                }
+            }
+            Encoding::Buffered(data) => data.extend_from_slice(buf),
+        }
+        self.crc.update(buf);
+        self.failed = false;
+        Ok(buf.len())
+    }
+
+    /// Writes out the coded bytes that are complete. The coder holds back
+    /// the bits that are not yet settled, so this does not make everything
+    /// written so far decodable.
+    fn flush (&mut self) -> Result<(), std::io::Error> {
+        if self.failed {
+            return Err(std::io::Error::other("an earlier write failed"));
+        }
+        self.failed = true;
+        self.bits_out.flush_bytes()?;
+        self.failed = false;
+        Ok(())
+    }
+}
+
+// The decoders of every mode, each able to stop after a few bytes.
+enum Decoding {
+    Cacm(ArithmeticDecoder<ContextModel>),
+    Range(RangeDecoder<ContextModel>),
+    Ppm(Ppm<ArithmeticDecoder>),
+    Sse(Box<Sse<ArithmeticDecoder>>),
+    Interleaved(InterleavedDecoder),
+    // Empty rANS streams have no frequency table.
+    Rans(Option<RansDecoder>),
+    Mixing(BinaryDecoder, Box<CmModel>),
+    Lz77(Box<Lz77Decoder>),
+    Bwt(Box<BwtDecoder>),
+}
+
+impl Decoding {
+    // Picks the decoder for `header` and reads the start of its stream.
+    fn begin (header: &Header, bits_in: &mut impl Next) -> Result<Self, Error> {
+        let unsupported = |what: &str| Error::unsupported(format!("unsupported {what}"));
+        let size = if header.counted() { COUNTED_SIZE } else { SIZE };
+        let order = header.parameter as usize;
+        let default_only = header.backend == Backend::Cacm87 && header.parameter == 0 && !header.counted();
+        let sse = header.flags & container::SSE != 0;
+        if sse && header.model != ModelId::Mixing && (header.model, header.backend) != (ModelId::Context, Backend::Cacm87) {
+            return Err(unsupported("options for this model"));
+        }
+        let window = order as u32;
+        let mut decoding = match (header.model, header.backend) {
+            (ModelId::Bwt, _) if default_only => Decoding::Bwt(Box::default()),
+            (ModelId::Lz77, Backend::Cacm87) if !header.counted() => {
+                if !(lz77::MIN_WINDOW_BITS..=lz77::MAX_WINDOW_BITS).contains(&window) {
+                    return Err(Error::unsupported("invalid LZ77 window"));
+                }
+                Decoding::Lz77(Box::new(Lz77Decoder::new(window)))
+            }
+            (ModelId::Mixing, _) if default_only => Decoding::Mixing(BinaryDecoder::new(), Box::new(CmModel::with_sse(sse))),
+            (ModelId::Ppm, Backend::Cacm87) if order <= crate::ppm::MAX_ORDER => Decoding::Ppm(Ppm::decoder(size, order)),
+            (ModelId::Interleaved, Backend::Cacm87) if header.parameter == 0 => {
+                return Ok(Decoding::Interleaved(InterleavedDecoder::from_stream(AdaptiveModel::new(size), bits_in)?));
+            }
+            (ModelId::Context, Backend::Cacm87) if order <= MAX_ORDER && sse => Decoding::Sse(Box::new(Sse::decoder(size, order))),
+            (ModelId::Context, Backend::Cacm87) if order <= MAX_ORDER => {
+                Decoding::Cacm(ArithmeticDecoder::with_model(ContextModel::new(size, order)))
+            }
+            (ModelId::Context, Backend::Range) if order <= MAX_ORDER => {
+                Decoding::Range(RangeDecoder::with_model(ContextModel::new(size, order)))
+            }
+            (ModelId::Context, Backend::Rans) if order == 0 && header.counted() => {
+                // rANS streams hold a frequency table ahead of the bytes.
+                if header.length == Some(0) {
+                    return Ok(Decoding::Rans(None));
+                }
+                let model = StaticModel::read(bits_in)?;
+                if model.size() > COUNTED_SIZE {
+                    return Err(Error::corrupt("frequency table larger than a byte"));
+                }
+                Decoding::Rans(Some(RansDecoder::new(model)))
+            }
+            (ModelId::Context | ModelId::Ppm, _) => return Err(unsupported("context order for this backend")),
+            _ => return Err(unsupported("options for this model")),
+        };
+        match &mut decoding {
+            Decoding::Cacm(decoder) => decoder.begin_symbols(bits_in)?,
+            Decoding::Range(decoder) => decoder.begin_symbols(bits_in)?,
+            Decoding::Ppm(decoder) => decoder.begin_symbols(bits_in)?,
+            Decoding::Sse(decoder) => decoder.begin_symbols(bits_in)?,
+            Decoding::Rans(Some(decoder)) => decoder.begin(bits_in)?,
+            Decoding::Mixing(decoder, _) => decoder.begin(bits_in)?,
+            Decoding::Lz77(decoder) => decoder.begin(bits_in)?,
+            Decoding::Bwt(decoder) => decoder.begin(bits_in)?,
+            Decoding::Interleaved(_) | Decoding::Rans(None) => {}
+        }
+        Ok(decoding)
+    }
+
+    // Decodes a byte or more into `out`. Returns `false` at the end of the
+    // stream; `remaining` counts down the bytes of counted streams.
+    fn step (&mut self, remaining: &mut Option<u64>, bits_in: &mut impl Next, out: &mut Vec<u8>) -> Result<bool, Error> {
+        if *remaining == Some(0) {
+            return Ok(false);
+        }
+        let s = match self {
+            Decoding::Cacm(decoder) => decoder.decode_symbol(bits_in)?,
+            Decoding::Range(decoder) => decoder.decode_symbol(bits_in)?,
+            Decoding::Ppm(decoder) => decoder.decode_symbol(bits_in)?,
+            Decoding::Sse(decoder) => decoder.decode_symbol(bits_in)?,
+            Decoding::Interleaved(decoder) => decoder.decode()?,
+            Decoding::Rans(decoder) => match decoder {
+                Some(decoder) => decoder.decode(bits_in)?,
+                None => return Err(Error::corrupt("rANS stream without a frequency table")),
+            },
+            Decoding::Mixing(decoder, model) => {
+                if decoder.decode_with_probability(MORE, bits_in)? {
+                    return Ok(false);
+                }
+                out.push(model.decode(decoder, bits_in)?);
+                return Ok(true);
+            }
+            Decoding::Lz77(decoder) => {
+                let mut bytes = Bytes(out);
+                return Ok(decoder.decode_next(bits_in, &mut bytes)?);
+            }
+            Decoding::Bwt(decoder) => match decoder.decode_block(bits_in)? {
+                Some(block) => {
+                    out.extend_from_slice(&block);
+                    return Ok(true);
+                }
+                None => return Ok(false),
+            },
+        };
+        match remaining {
+            Some(n) => *n -= 1,
+            None if s == EOF => return Ok(false),
+            None => {}
+        }
+        if s >= COUNTED_SIZE {
+            return Err(Error::corrupt("symbol out of range"));
+        }
+        out.push(s as u8);
+        Ok(true)
+    }
+}
+
+// A sink for decoders that push whole bytes.
+struct Bytes<'a>(&'a mut Vec<u8>);
+
+impl Push for Bytes<'_> {
+    fn push_bit(&mut self, _: bool) -> Result<(), std::io::Error> {
+        unreachable!("decoders push whole bytes")
+    }
+
+    fn push_byte(&mut self, byte: u8) -> Result<(), std::io::Error> {
+        self.0.push(byte);
+        Ok(())
+    }
+}
+
+// The stream body, with the checksum trailer held back if there is one.
+enum Source<R>
+where R: BufRead
+{
+    Plain(Input<R>),
+    Checksummed(Trailer<Input<R>>),
+}
+
+impl<R> Next for Source<R>
+where R: BufRead
+{
+    fn next_bit (&mut self) -> Result<Option<bool>, std::io::Error> {
+        match self {
+            Source::Plain(bits_in) => bits_in.next_bit(),
+            Source::Checksummed(bits_in) => bits_in.next_bit(),
+        }
+    }
+
+    fn next_byte (&mut self) -> Result<Option<u8>, std::io::Error> {
+        match self {
+            Source::Plain(bits_in) => bits_in.next_byte(),
+            Source::Checksummed(bits_in) => bits_in.next_byte(),
+        }
+    }
+}
+
+impl<R> Source<R>
+where R: BufRead
+{
+    fn into_inner (self) -> R {
+        match self {
+            Source::Plain(bits_in) => bits_in.into_inner(),
+            Source::Checksummed(bits_in) => bits_in.into_inner().into_inner(),
+        }
+    }
+}
+
+// Bytes decoded per step, so reads of a few bytes stay cheap.
+const CHUNK: usize = 4096;
+
+/// A reader that decompresses a stream produced by
+/// [`encode_routine_with`](crate::encode_routine_with) or an
+/// [`ArithmeticWriter`]. The stream may be followed by other data, which
+/// [`ArithmeticReader::into_inner`] gives back.
+///
+/// Reads fail with an [`Error`] wrapped in a `std::io::Error`; convert it
+/// back with `Error::from`.
+pub struct ArithmeticReader<R>
+where R: BufRead
+{
+    bits_in: Source<R>,
+    header: Header,
+    decoding: Decoding,
+    remaining: Option<u64>,
+    decoded: Vec<u8>,
+    pos: usize,
+    total: u64,
+    limit: u64,
+    crc: Crc32,
+    done: bool
+}
+
+impl<R> ArithmeticReader<R>
+where R: BufRead
+{
+    /// Reads the header of the stream in `inner`, with the default
+    /// [`DecodeOptions`].
+    pub fn new (inner: R) -> Result<Self, Error> {
+        Self::with_options(inner, &DecodeOptions::default())
+    }
+
+    /// Reads the header of the stream in `inner`. Fails if this version
+    /// cannot decode the stream, or if it announces more bytes than
+    /// `options` allow.
+    pub fn with_options (inner: R, options: &DecodeOptions) -> Result<Self, Error> {
+        let mut bits_in = Input::new(inner)?;
+        let header = Header::read(&mut bits_in)?;
+        let limit = options.max_size.unwrap_or(u64::MAX);
+        if header.length.is_some_and(|length| length > limit) {
+            return Err(Error::TooLarge(limit));
+        }
+        let mut bits_in = if header.checksum() {
+            Source::Checksummed(Trailer::new(bits_in)?)
+        } else {
+            Source::Plain(bits_in)
+        };
+        let decoding = Decoding::begin(&header, &mut bits_in)?;
+        Ok(ArithmeticReader {
+            bits_in,
+            remaining: header.length,
+            header,
+            decoding,
+            decoded: Vec::with_capacity(CHUNK),
+            pos: 0,
+            total: 0,
+            limit,
+            crc: Crc32::new(),
+            done: false
+        })
+    }
+
+    /// Returns the header of the stream.
+    pub fn header (&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the inner reader. Once everything has been read, it is left
+    /// right after the end of the stream, as decoding reads no further.
+    pub fn into_inner (self) -> R {
+        self.bits_in.into_inner()
+    }
+
+    // Refills the buffer of decoded bytes, checking the size limit and at
+    // the end the checksum.
+    fn fill (&mut self) -> Result<(), Error> {
+        self.decoded.clear();
+        self.pos = 0;
+        while self.decoded.len() < CHUNK {
+            if !self.decoding.step(&mut self.remaining, &mut self.bits_in, &mut self.decoded)? {
+                self.done = true;
+                break;
+            }
+        }
+        self.total += self.decoded.len() as u64;
+        if self.total > self.limit {
+            return Err(Error::TooLarge(self.limit));
+        }
+        self.crc.update(&self.decoded);
+        if let (true, Source::Checksummed(bits_in)) = (self.done, &mut self.bits_in) {
+            ChecksumError::check(Checked::Data, bits_in.finish()?, self.crc.value())?;
+        }
+        Ok(())
+    }
+}
+
+impl<R> Read for ArithmeticReader<R>
+where R: BufRead
+{
+    fn read (&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        while self.pos == self.decoded.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample () -> Vec<u8> {
+        let mut data = b"\0\x01 the quick brown fox jumps over the lazy dog ".repeat(300);
+        data.extend((0..=255u8).cycle().take(5000));
+        data
+    }
+
+    #[test]
+    fn test_writer_chunks () {
+        let data = sample();
+        for options in [
+            Options::default(),
+            Options { backend: Backend::Range, order: 2, checksum: true, ..Options::default() },
+            Options { ppm: true, order: 4, ..Options::default() },
+            Options { cm: true, sse: true, ..Options::default() },
+            Options { lz77: true, ..Options::default() },
+            Options { counted: true, checksum: true, ..Options::default() },
+            Options { bwt: true, block_size: 1000, ..Options::default() },
+            Options { bwt: true, block_size: 777, checksum: true, ..Options::default() },
+        ] {
+            let mut expected = Vec::new();
+            crate::encode_routine_with(&mut data.as_slice(), &mut expected, &options).unwrap();
+            let mut writer = ArithmeticWriter::with_options(Vec::new(), &options).unwrap();
+            for chunk in data.chunks(777) {
+                writer.write_all(chunk).unwrap();
+                writer.flush().unwrap();
+            }
+            assert_eq!(writer.finish().unwrap(), expected);
+        }
+    }
+    #[test]
+    fn test_writer_poisoned () {
+        struct Broken;
+        impl Write for Broken {
+            fn write (&mut self, _: &[u8]) -> Result<usize, std::io::Error> {
+                Err(std::io::Error::other("broken"))
+            }
+            fn flush (&mut self) -> Result<(), std::io::Error> {
+                Ok(())
+            }
+        }
+        let data = sample();
+        let mut writer = ArithmeticWriter::new(Broken);
+        let err = data.chunks(100).find_map(|chunk| writer.write_all(chunk).err()).unwrap();
+        assert_eq!(err.to_string(), "broken");
+        assert_eq!(writer.write(b"x").unwrap_err().to_string(), "an earlier write failed");
+        assert!(writer.flush().is_err());
+        assert!(writer.finish().is_err());
+    }
+    #[test]
+    fn test_reader_round_trip () {
+        let data = sample();
+        for options in [
+            Options::default(),
+            Options { backend: Backend::Rans, checksum: true, ..Options::default() },
+            Options { lanes: 4, counted: true, ..Options::default() },
+            Options { bwt: true, block_size: 5000, ..Options::default() },
+            Options { cm: true, ..Options::default() },
+        ] {
+            let mut coded = Vec::new();
+            crate::encode_routine_with(&mut data.as_slice(), &mut coded, &options).unwrap();
+            let mut reader = ArithmeticReader::new(coded.as_slice()).unwrap();
+            let mut decoded = Vec::new();
+            let mut buf = [0; 100];
+            loop {
+                let n = reader.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                decoded.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(decoded, data);
+        }
+    }
+    #[test]
+    fn test_reader_errors () {
+        let mut writer = ArithmeticWriter::with_options(Vec::new(), &Options { checksum: true, ..Options::default() }).unwrap();
+        writer.write_all(&sample()).unwrap();
+        let mut coded = writer.finish().unwrap();
+        let last = coded.len() - 1;
+        coded[last] ^= 1;
+        let err = ArithmeticReader::new(coded.as_slice()).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(Error::from(err), Error::Checksum(ChecksumError { part: Checked::Data, .. })));
+        let limit = DecodeOptions { max_size: Some(1000) };
+        let err = ArithmeticReader::with_options(coded.as_slice(), &limit).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(Error::from(err), Error::TooLarge(1000)));
+    }
+    #[test]
+    fn test_reader_wide_table () {
+        let mut bits_out = Output::new(Vec::new(), 8 * 1024);
+        Header::new(Backend::Rans, ModelId::Context, 0, container::COUNTED, Some(10)).write(&mut bits_out).unwrap();
+        bits_out.push_varint(300).unwrap();
+        for s in 0..300 {
+            bits_out.push_varint(if s == 299 { 32768 - 299 * 100 } else { 100 }).unwrap();
+        }
+        for _ in 0..8 {
+            bits_out.push_byte(0xff).unwrap();
+        }
+        let coded = bits_out.into_inner().unwrap();
+        let err = ArithmeticReader::new(coded.as_slice()).err();
+        assert!(matches!(err, Some(Error::Corrupt(_))));
+    }
+}